@@ -0,0 +1,104 @@
+use std::{sync::{Arc, RwLock}, time::Duration};
+use solana_client::{client_error::Result as ClientResult, nonblocking::rpc_client::RpcClient};
+use solana_sdk::hash::Hash;
+use tokio::{sync::Mutex, time::{Instant, sleep}};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+/// A cached blockhash is refetched on use if background refreshes fail for so long,
+/// a blockhash expires in 150 blocks (60-90 seconds).
+const MAX_AGE: Duration = Duration::from_secs(20);
+/// Block height checks of all the senders within the period share a request.
+const BLOCK_HEIGHT_MAX_AGE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RecentBlockhash {
+    pub(crate) hash: Hash,
+    /// Transactions with this blockhash are rejected after the block height
+    pub(crate) last_valid_block_height: u64,
+}
+
+/// Latest blockhash shared by all the senders of a run so they don't request it for every transaction.
+/// It's fetched on first use and refreshed in background until the cache is dropped.
+pub(crate) struct BlockhashCache {
+    rpc_client: Arc<RpcClient>,
+    latest: RwLock<Option<(RecentBlockhash, Instant)>>,
+    /// lets a single request fetch the expired blockhash for all the waiting senders
+    fetching: Mutex<()>,
+    block_height: Mutex<Option<(u64, Instant)>>,
+}
+
+impl BlockhashCache {
+    pub(crate) fn start(rpc_client: Arc<RpcClient>) -> Arc<Self> {
+        let cache = Arc::new(Self {
+            rpc_client,
+            latest: RwLock::new(None),
+            fetching: Mutex::new(()),
+            block_height: Mutex::new(None),
+        });
+        let weak = Arc::downgrade(&cache);
+        tokio::spawn(async move {
+            loop {
+                sleep(REFRESH_INTERVAL).await;
+                let Some(cache) = weak.upgrade() else { break };
+                // not used yet
+                if cache.cached().is_none() {
+                    continue
+                }
+                // a failed refresh is retried by the next round or on use when the blockhash is too old
+                let _ = cache.fetch().await;
+            }
+        });
+        cache
+    }
+
+    pub(crate) async fn get(&self) -> ClientResult<RecentBlockhash> {
+        if let Some(blockhash) = self.cached() {
+            return Ok(blockhash)
+        }
+        let _fetching = self.fetching.lock().await;
+        match self.cached() {
+            Some(blockhash) => Ok(blockhash),
+            None => self.fetch().await,
+        }
+    }
+
+    /// A blockhash valid longer than the expired one, it's fetched if the cached one isn't.
+    pub(crate) async fn get_newer_than(&self, expired: &RecentBlockhash) -> ClientResult<RecentBlockhash> {
+        let _fetching = self.fetching.lock().await;
+        match self.cached() {
+            Some(blockhash) if blockhash.last_valid_block_height > expired.last_valid_block_height => Ok(blockhash),
+            _ => self.fetch().await,
+        }
+    }
+
+    /// Whether transactions with the blockhash can't get into a block anymore.
+    pub(crate) async fn is_expired(&self, blockhash: &RecentBlockhash) -> ClientResult<bool> {
+        Ok(self.block_height().await? > blockhash.last_valid_block_height)
+    }
+
+    async fn block_height(&self) -> ClientResult<u64> {
+        let mut block_height = self.block_height.lock().await;
+        if let Some((height, fetched_at)) = *block_height {
+            if fetched_at.elapsed() < BLOCK_HEIGHT_MAX_AGE {
+                return Ok(height)
+            }
+        }
+        let height = self.rpc_client.get_block_height().await?;
+        *block_height = Some((height, Instant::now()));
+        Ok(height)
+    }
+
+    fn cached(&self) -> Option<RecentBlockhash> {
+        self.latest.read().expect("not poisoned")
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < MAX_AGE)
+            .map(|(blockhash, _)| blockhash)
+    }
+
+    async fn fetch(&self) -> ClientResult<RecentBlockhash> {
+        let (hash, last_valid_block_height) = self.rpc_client
+            .get_latest_blockhash_with_commitment(self.rpc_client.commitment()).await?;
+        let blockhash = RecentBlockhash { hash, last_valid_block_height };
+        *self.latest.write().expect("not poisoned") = Some((blockhash, Instant::now()));
+        Ok(blockhash)
+    }
+}
@@ -1,7 +1,10 @@
 use std::path::PathBuf;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
-use crate::config::PubkeySerde;
+use solana_sdk::{commitment_config::CommitmentLevel, signature::Signature};
+use crate::config::{AddressRef, KeypairSource, PubkeySerde};
+use crate::sender::Route;
+use crate::wallet::{FileNaming, SaveOptions};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -9,11 +12,88 @@ pub(crate) struct Cli {
     #[arg(long = "config", short = 'c', value_name = "config", default_value = "env:TEST_TASK_CONFIG_FILE")]
     pub config_file: String,
 
-    
+    /// Config profile (`profiles` section) or a cluster moniker: localnet, devnet, testnet, mainnet-beta
+    #[arg(long, short = 'p', global = true, env = "TEST_TASK_PROFILE")]
+    pub(crate) profile: Option<String>,
+
+    #[command(flatten)]
+    pub(crate) overrides: ConfigOverrides,
+
     #[command(subcommand)]
     pub(crate) command: SubCmd,
 }
 
+/// Config values overrides, they take precedence over the config file (environment variables over the file,
+/// command line arguments over environment variables).
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Config overrides")]
+pub(crate) struct ConfigOverrides {
+    /// RPC endpoint URL (`rpc.uri`)
+    #[arg(long = "url", value_name = "URL", global = true, env = "TEST_TASK_RPC_URL")]
+    pub(crate) rpc_url: Option<url::Url>,
+    /// Additional RPC endpoints with weight 1 replacing the config ones (`rpc.endpoints`),
+    /// comma separated in the env variable
+    #[arg(long = "endpoint", value_name = "URL", global = true, env = "TEST_TASK_RPC_ENDPOINTS", value_delimiter = ',')]
+    pub(crate) rpc_endpoints: Vec<url::Url>,
+    /// WebSocket endpoint URL of signature subscriptions (`rpc.ws_uri`)
+    #[arg(long = "ws-url", value_name = "URL", global = true, env = "TEST_TASK_WS_URL")]
+    pub(crate) ws_url: Option<url::Url>,
+    /// Commitment level of RPC requests (`rpc.commitment`): processed, confirmed or finalized
+    #[arg(long, global = true, env = "TEST_TASK_COMMITMENT")]
+    pub(crate) commitment: Option<CommitmentLevel>,
+    /// Commitment level sent transactions are waited for (`rpc.wait_for`)
+    #[arg(long, global = true, env = "TEST_TASK_WAIT_FOR")]
+    pub(crate) wait_for: Option<CommitmentLevel>,
+    /// Max RPC requests per second (`rpc.rate_limit_per_sec`)
+    #[arg(long, global = true, env = "TEST_TASK_RPC_RATE_LIMIT")]
+    pub(crate) rate_limit: Option<u32>,
+    /// Max simultaneous RPC requests (`rpc.max_concurrent_requests`)
+    #[arg(long, global = true, env = "TEST_TASK_RPC_MAX_CONCURRENT_REQUESTS")]
+    pub(crate) max_concurrent_requests: Option<usize>,
+    /// Max retries of a failed RPC request (`rpc.retry.max_retries`)
+    #[arg(long, global = true, env = "TEST_TASK_RPC_MAX_RETRIES")]
+    pub(crate) max_retries: Option<u32>,
+    /// Max transaction signing attempts (`rpc.max_send_attempts`)
+    #[arg(long, global = true, env = "TEST_TASK_MAX_SEND_ATTEMPTS")]
+    pub(crate) max_send_attempts: Option<u32>,
+    /// Token owner keypair source (`token.owner`)
+    #[arg(long, global = true, env = "TEST_TASK_TOKEN_OWNER", hide_env_values = true)]
+    pub(crate) token_owner: Option<KeypairSource>,
+    /// Token mint keypair source (`token.mint`)
+    #[arg(long, global = true, env = "TEST_TASK_TOKEN_MINT", hide_env_values = true)]
+    pub(crate) token_mint: Option<KeypairSource>,
+    /// Tested token mint address (`test.mint`)
+    #[arg(long, global = true, env = "TEST_TASK_TEST_MINT")]
+    pub(crate) test_mint: Option<PubkeySerde>,
+    /// SOL transfer test cases as a YAML sequence (`test.transfers.sols`)
+    #[arg(long, global = true, env = "TEST_TASK_SOL_TRANSFERS")]
+    pub(crate) sol_transfers: Option<String>,
+    /// Token transfer test cases as a YAML sequence (`test.transfers.tokens`)
+    #[arg(long, global = true, env = "TEST_TASK_TOKEN_TRANSFERS")]
+    pub(crate) token_transfers: Option<String>,
+    /// BIP39 seed phrase of the derived wallets (`seed.phrase`)
+    #[arg(long, global = true, env = "TEST_TASK_SEED_PHRASE", hide_env_values = true)]
+    pub(crate) seed_phrase: Option<String>,
+    /// BIP39 seed passphrase (`seed.passphrase`)
+    #[arg(long, global = true, env = "TEST_TASK_SEED_PASSPHRASE", hide_env_values = true)]
+    pub(crate) seed_passphrase: Option<String>,
+    /// Wallet keypair sources replacing the config ones (`wallets`), comma separated in the env variable
+    #[arg(long = "wallet", value_name = "KEYPAIR", global = true, env = "TEST_TASK_WALLETS", value_delimiter = ',', hide_env_values = true)]
+    pub(crate) wallets: Vec<KeypairSource>,
+    /// Encrypted keystore file (`keystore`)
+    #[arg(long = "keystore", value_name = "PATH", global = true, env = "TEST_TASK_KEYSTORE")]
+    pub(crate) keystore_path: Option<PathBuf>,
+    /// Address book entries `label=pubkey` added to the config ones (`address_book`)
+    #[arg(long = "address", value_name = "LABEL=PUBKEY", global = true, env = "TEST_TASK_ADDRESS_BOOK", value_delimiter = ',', value_parser = parse_address_book_entry)]
+    pub(crate) address_book: Vec<(String, PubkeySerde)>,
+}
+
+fn parse_address_book_entry(value: &str) -> Result<(String, PubkeySerde), String> {
+    let (label, pubkey) = value.split_once('=')
+        .ok_or_else(|| format!("expected `label=pubkey`, got `{value}`"))?;
+    let pubkey = pubkey.trim().parse::<PubkeySerde>().map_err(|e| e.to_string())?;
+    Ok((label.trim().to_string(), pubkey))
+}
 
 #[derive(Subcommand, Debug)]
 pub(crate) enum SubCmd {
@@ -28,8 +108,15 @@ pub(crate) enum SubCmd {
     /// Wallets management
     Wallet { #[command(subcommand)] command: WalletSubCmd },
 
-    /// Show config values
-    ShowConfig,
+    /// Show config values (secret keys are replaced with public keys)
+    ShowConfig {
+        /// Show secret keys and the seed phrase
+        #[arg(long)] reveal_secrets: bool,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Yaml)] format: OutputFormat,
+    },
+
+    /// Config management
+    Config { #[command(subcommand)] command: ConfigSubCmd },
 
     /// Show SOL balances
     Balances,
@@ -51,6 +138,37 @@ pub(crate) enum WalletSubCmd {
         count: usize,
         /// Dir to save wallets in solana-cli compatible json format
         save_to: Option<PathBuf>,
+        #[command(flatten)] save_args: SaveArgs,
+        /// Generate a BIP39 mnemonic and derive wallets from it (m/44'/501'/i'/0')
+        #[arg(long)] mnemonic: bool,
+        /// Mnemonic word count (12 or 24)
+        #[arg(long, default_value_t = 12, requires = "mnemonic")] words: usize,
+        /// Optional BIP39 passphrase
+        #[arg(long, requires = "mnemonic", env = "TEST_TASK_MNEMONIC_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+        /// Append generated wallets to the config "wallets" list (the original config is kept as <config>.bak)
+        #[arg(long, conflicts_with_all = ["save_to", "mnemonic", "force", "naming", "no_secrets"])] append_to_config: bool,
+    },
+    /// Remove a wallet from the config "wallets" list (the original config is kept as <config>.bak)
+    Remove {
+        /// wallet index or name
+        wallet: AddressRef,
+    },
+    /// Recover wallets derived from an existing seed phrase (m/44'/501'/i'/0')
+    Recover {
+        /// Count of derived keypairs
+        count: u32,
+        /// Dir to save wallets in solana-cli compatible json format
+        save_to: Option<PathBuf>,
+        #[command(flatten)] save_args: SaveArgs,
+        /// BIP39 seed phrase (not the config `seed.phrase` override)
+        #[arg(long, env = "TEST_TASK_RECOVER_PHRASE", hide_env_values = true)]
+        phrase: String,
+        /// Optional BIP39 passphrase (not the config `seed.passphrase` override)
+        #[arg(long, env = "TEST_TASK_RECOVER_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+        /// First derivation index
+        #[arg(long, default_value_t = 0)] start: u32,
     },
     /// List wallets
     List {
@@ -58,17 +176,82 @@ pub(crate) enum WalletSubCmd {
         #[arg(long)] pubkey: bool,
         /// show keypair
         #[arg(long)] keypair: bool,
+        /// show where the keypair comes from (inline, file, env, derived)
+        #[arg(long)] source: bool,
     },
     /// Save wallets from the config as solana-cli compatible json files
     Save {
         /// Directory storing wallet json files
-        target: PathBuf
+        target: PathBuf,
+        #[command(flatten)] save_args: SaveArgs,
+    },
+    /// Grind vanity wallets (keypairs with the address matching a prefix and/or a suffix)
+    Grind {
+        /// Address prefix
+        #[arg(long, default_value = "")] prefix: String,
+        /// Address suffix
+        #[arg(long, default_value = "")] suffix: String,
+        /// Case-insensitive matching
+        #[arg(long)] ignore_case: bool,
+        /// Count of generating keypairs
+        #[arg(long, default_value_t = 1)] count: usize,
+        /// Dir to save wallets in solana-cli compatible json format
+        save_to: Option<PathBuf>,
+        #[command(flatten)] save_args: SaveArgs,
     },
     /// Read a keypair json file (solana-cli compatible) and print it's buffer in a base58 encoded string
     Read {
         /// keypair file path
         path: PathBuf,
-    }
+    },
+    /// Import solana-cli compatible keypair json files into the config wallets (duplicates are skipped)
+    Import {
+        /// Directory with keypair files or a file name pattern (e.g. "workdir/wallets/p1w*.json")
+        path: PathBuf,
+        /// Reference the files ("file:<path>") instead of embedding the keypairs
+        #[arg(long)] link: bool,
+    },
+    /// Move inline wallets of the config into an encrypted keystore file and reference it from the config
+    Encrypt {
+        /// keystore file path
+        keystore: PathBuf,
+    },
+    /// Move wallets from the config keystore back into the config as inline keypairs
+    Decrypt,
+    /// Sign an off-chain message (Solana off-chain message format) by a wallet from the config
+    SignMessage {
+        /// wallet index or name
+        signer: AddressRef,
+        #[command(flatten)] message: MessageArgs,
+    },
+    /// Verify an off-chain message signature
+    VerifyMessage {
+        /// signer public key
+        pubkey: PubkeySerde,
+        /// base58 encoded signature
+        signature: Signature,
+        #[command(flatten)] message: MessageArgs,
+    },
+}
+
+/// Options of saving wallets as json files
+#[derive(Args, Debug, Clone)]
+pub(crate) struct SaveArgs {
+    /// Overwrite existing wallet files
+    #[arg(long)] pub(crate) force: bool,
+    /// Wallet file naming
+    #[arg(long, value_enum, default_value_t = FileNaming::Index)] pub(crate) naming: FileNaming,
+    /// Print public keys instead of secret keys of the saved wallets
+    #[arg(long)] pub(crate) no_secrets: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+#[group(required = true, multiple = false)]
+pub(crate) struct MessageArgs {
+    /// message text
+    pub(crate) text: Option<String>,
+    /// read the message from a file
+    #[arg(long)] pub(crate) file: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -76,14 +259,66 @@ pub(crate) enum TokenSubCmd {
     /// Deploys token (uses config "token.mint" keypair for mint deployment)
     Deploy,
     /// Mints tokens and calculates holder's vault PDA (token account) and send there
-    Mint { holder: PubkeySerde, amount: f64 },
+    Mint {
+        /// wallet index or name, address book label or a public key
+        holder: AddressRef,
+        amount: f64,
+    },
     /// Show token balances of all holders (config.wallets)
     Balances,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub(crate) enum OutputFormat {
+    Yaml,
+    Json,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub(crate) enum ConfigSubCmd {
+    /// Write a starter config (the `--config` path) with fresh token keypairs, wallets and sample test cases
+    Init {
+        /// Count of generated wallets
+        #[arg(long = "wallets", default_value_t = 20)] wallet_count: usize,
+        /// Cluster moniker (localnet, devnet, testnet, mainnet-beta) or RPC URL
+        #[arg(long, default_value = "localnet")] cluster: String,
+        /// Overwrite the existing config file
+        #[arg(long)] force: bool,
+    },
+    /// Print JSON Schema of the config file format
+    Schema,
+    /// Check the config file with all its profiles, overrides aren't applied: keypairs, test transfer parties
+    /// and amounts, the tested mint, duplicate wallets, RPC availability
+    Validate {
+        /// Don't check the RPC endpoints
+        #[arg(long)] offline: bool,
+    },
+}
+
 #[derive(Subcommand, Debug, Clone)]
 pub(crate) enum TestSubCmd {
-    Transfer { #[command(subcommand)] command: TestTransferSubCmd }
+    Transfer {
+        /// Run identifier used in memos (generated if omitted)
+        #[arg(long)] run_id: Option<String>,
+        /// Send path of test transactions
+        #[arg(long, value_enum, default_value_t = SendVia::Rpc)] send_via: SendVia,
+        /// Max transfers in flight at a time
+        #[arg(long, default_value_t = 32, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        concurrency: usize,
+        /// Give up on a transfer after the seconds (no limit by default)
+        #[arg(long, value_name = "SECONDS")] timeout: Option<u64>,
+        #[command(subcommand)] command: TestTransferSubCmd
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SendVia {
+    /// `sendTransaction` RPC requests
+    Rpc,
+    /// QUIC connections to TPU ports of the upcoming leaders
+    Tpu,
+    /// Alternate RPC and TPU between test cases to compare their confirmation times
+    Both,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -93,3 +328,30 @@ pub(crate) enum TestTransferSubCmd {
     /// Test batched tokens transfer
     Tokens,
 }
+
+impl From<SaveArgs> for SaveOptions {
+    fn from(args: SaveArgs) -> Self {
+        SaveOptions { force: args.force, naming: args.naming, no_secrets: args.no_secrets }
+    }
+}
+
+impl SendVia {
+    pub(crate) fn route(self, case: usize) -> Route {
+        match self {
+            SendVia::Rpc => Route::Rpc,
+            SendVia::Tpu => Route::Tpu,
+            SendVia::Both if case.is_multiple_of(2) => Route::Rpc,
+            SendVia::Both => Route::Tpu,
+        }
+    }
+}
+
+impl MessageArgs {
+    pub(crate) fn read(&self) -> std::io::Result<Vec<u8>> {
+        match (&self.text, &self.file) {
+            (_, Some(path)) => std::fs::read(path),
+            (Some(text), None) => Ok(text.as_bytes().to_vec()),
+            (None, None) => unreachable!("clap requires one of the message arguments"),
+        }
+    }
+}
@@ -1,47 +1,106 @@
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 use futures::future::join_all;
 use snafu::ResultExt as _;
 use solana_client::nonblocking::rpc_client::{self, RpcClient};
 use solana_sdk::{
-    commitment_config::CommitmentConfig,
+    commitment_config::CommitmentLevel,
+    pubkey::Pubkey,
     signer::Signer,
-    signature::Keypair,
+    signature::{Keypair, Signature},
 };
 use tokio::time::Instant;
-use crate::{MainResult, config::{
+use crate::{MainResult, cli::{MessageArgs, OutputFormat, SendVia}, sender::Route, config_file::{self, ConfigFile}, keystore, config::{
     self,
     Config,
+    AddressRef,
     KeypairSerde,
     PubkeySerde,
     TestTransferConfig
-}, token, worker, wallet, ConfigSnafu, WalletSnafu, TokenSnafu, RpcSnafu, lamports_to_sol, sol_to_lamports, MainError};
+}, blockhash, confirm, rpc, sender, token, worker, wallet::{self, SaveOptions}, ConfigSnafu, ConfigFileSnafu, KeystoreSnafu, WalletSnafu, TokenSnafu, RpcSnafu, ConfirmSnafu, SendSnafu, lamports_to_sol, sol_to_lamports, MainError};
 
 pub(crate) struct CmdHandlers {
     pub(crate) config: Config,
     token_owner: Arc<Keypair>,
     token_mint: Arc<Keypair>,
+    rpc_stats: Arc<rpc::RpcStats>,
+    /// made on first use, so commands without RPC requests don't start endpoint health checks
+    rpc_client: std::sync::OnceLock<Arc<RpcClient>>,
+    confirmer: std::sync::OnceLock<Arc<confirm::Confirmer>>,
+    blockhashes: std::sync::OnceLock<Arc<blockhash::BlockhashCache>>,
 }
 
 impl CmdHandlers {
 
     pub(crate) fn new(config: Config) -> Self {
         Self {
-            token_owner: Arc::new(config.token.owner.clone().0),
-            token_mint: Arc::new(config.token.mint.clone().0),
+            token_owner: Arc::new(config.token_keypairs().owner.clone().0),
+            token_mint: Arc::new(config.token_keypairs().mint.clone().0),
+            rpc_stats: Arc::default(),
+            rpc_client: std::sync::OnceLock::new(),
+            confirmer: std::sync::OnceLock::new(),
+            blockhashes: std::sync::OnceLock::new(),
             config,
         }
     }
 
-    pub(crate) fn show_config(&self) -> MainResult<()> {
-        println!("{:#?}", self.config);
+    pub(crate) fn show_config(&self, reveal_secrets: bool, format: OutputFormat) -> MainResult<()> {
+        let value = self.config.to_value(reveal_secrets).context(ConfigSnafu)?;
+        match format {
+            OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&value).expect("YAML value is serializable")),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&value).expect("YAML value is serializable")),
+        }
         Ok(())
     }
 
+    /// The RPC client shared by all the requests of the run.
     pub(crate) fn connect(&self) -> Arc<RpcClient> {
-        Arc::new(RpcClient::new(self.config.rpc.uri.0.to_string()))
+        self.rpc_client
+            .get_or_init(|| Arc::new(rpc::connect(&self.config.rpc, self.rpc_stats.clone())))
+            .clone()
+    }
+
+    /// The confirmer shared by all the transactions of the run, so their statuses are polled together.
+    fn confirmer(&self) -> Arc<confirm::Confirmer> {
+        self.confirmer
+            .get_or_init(|| Arc::new(confirm::Confirmer::new(self.connect(), &self.config.rpc)))
+            .clone()
+    }
+
+    /// The blockhash cache shared by the token client and the transaction sender.
+    fn blockhashes(&self) -> Arc<blockhash::BlockhashCache> {
+        self.blockhashes
+            .get_or_init(|| blockhash::BlockhashCache::start(self.connect()))
+            .clone()
+    }
+
+    fn token(&self) -> token::Token {
+        token::Token::new(self.connect(), self.blockhashes(), self.token_mint.pubkey(), self.token_owner.clone())
+    }
+
+    async fn transaction_sender(
+        &self,
+        client: Arc<RpcClient>,
+        confirmer: Arc<confirm::Confirmer>,
+        send_via: SendVia,
+    ) -> MainResult<Arc<sender::TransactionSender>> {
+        let tx_sender = sender::TransactionSender::new(client.clone(), self.blockhashes(), confirmer, self.config.rpc.max_send_attempts);
+        if send_via == SendVia::Rpc {
+            return Ok(Arc::new(tx_sender))
+        }
+        eprintln!("Connecting to TPU of the upcoming leaders...");
+        let tpu = sender::connect_tpu(client, &self.config.rpc).await.context(SendSnafu)?;
+        Ok(Arc::new(tx_sender.with_tpu(tpu)))
+    }
+
+    /// Prints RPC transport statistics of the run if there were any requests.
+    pub(crate) fn print_rpc_stats(&self) {
+        if self.rpc_stats.requests() > 0 {
+            eprintln!("{}", self.rpc_stats);
+        }
     }
 
     pub(crate) async fn print_sol_balances(&self) -> MainResult<()> {
@@ -50,22 +109,22 @@ impl CmdHandlers {
         // let mut results: Vec<u64> = Vec::new();
         for (i, KeypairSerde(wallet)) in self.config.wallets.0.iter().enumerate() {
             let (pk, client) = (wallet.pubkey(), client.clone());
+            let address = self.config.display_address(&pk);
             handles.push(async move {
-                (i, pk, client.get_balance(&pk).await)
+                (i, address, client.get_balance(&pk).await)
             });
         }
         let results = join_all(handles).await;
 
-        for (i, pk, res) in results {
+        for (i, address, res) in results {
             match res {
-                Ok(balance) => println!("{i}. {pk}: {}", crate::lamports_to_sol(balance)),
-                Err(err) => println!("{i}. {pk}: error: {err}"),
+                Ok(balance) => println!("{i}. {address}: {}", crate::lamports_to_sol(balance)),
+                Err(err) => println!("{i}. {address}: error: {err}"),
             }
         }
         Ok(())
     }
 
-
     pub(crate) async fn airdrop(&self, sols_amount: f64, confirm: bool) -> MainResult<()> {
         let client = self.connect();
         let lamports = f64::floor(sols_amount * 1_000_000_000f64) as u64;
@@ -75,35 +134,32 @@ impl CmdHandlers {
             .iter().enumerate()
             .map(|(i, KeypairSerde(kp))| (format!("{i}. "), kp))
             .collect::<Vec<_>>();
-        wallets.push(("token:owner. ".into(), &self.config.token.owner.0));
+        wallets.push(("token:owner. ".into(), self.token_owner.as_ref()));
         for (pfx, wallet) in wallets {
             let (pk, client) = (wallet.pubkey(), client.clone());
+            let address = self.config.display_address(&pk);
             handles.push(async move {
-                (pfx, pk, client.request_airdrop(&pk, lamports).await)
+                (pfx, address, client.request_airdrop(&pk, lamports).await)
             });
         }
         let results = join_all(handles).await;
         if confirm {
             eprintln!("Waiting for confirmation of all transactions...");
-            let mut confirmation_handlers = Vec::new();
+            let mut sent = Vec::new();
             for (pfx, pk, res) in results {
                 match res {
-                    Ok(tx) => {
-                        let client = client.clone();
-                        confirmation_handlers.push(async move {
-                            let res = client.poll_for_signature_with_commitment(&tx, CommitmentConfig::finalized()).await;
-                            match res {
-                                Ok(_) => println!("{pfx}{pk}: tx id = {tx} - OK"),
-                                Err(err) => println!("{pfx}{pk}: tx id = {tx}: error: {err}"),
-                            };
-                            // (i, pk, tx, res)
-                        })
-                    },
+                    Ok(tx) => sent.push((pfx, pk, tx)),
                     Err(err) => eprintln!("{pfx}{pk}: error: {err}"),
                 }
             }
-            let _confirmation_results = join_all(confirmation_handlers).await;
-            // for (i, pk, tx, res) in confirmation_results {}
+            let signatures = sent.iter().map(|(_, _, tx)| *tx).collect::<Vec<_>>();
+            let confirmation_results = self.confirmer().confirm_all(&signatures, self.config.rpc.wait_for).await;
+            for ((pfx, pk, tx), res) in sent.into_iter().zip(confirmation_results) {
+                match res {
+                    Ok(_) => println!("{pfx}{pk}: tx id = {tx} - OK"),
+                    Err(err) => println!("{pfx}{pk}: tx id = {tx}: error: {err}"),
+                };
+            }
         } else {
             for (pfx, pk, res) in results {
                 match res {
@@ -115,10 +171,16 @@ impl CmdHandlers {
         Ok(())
     }
 
-    pub(crate) fn print_wallets(&self, pubkey: bool, keypair: bool) -> MainResult<()> {
-        for kp in self.config.wallets.0.iter() {
-            if pubkey == keypair {
-                println!("{} | {}", kp.pubkey(), kp)
+    pub(crate) fn print_wallets(&self, pubkey: bool, keypair: bool, source: bool) -> MainResult<()> {
+        for (kp, label) in self.config.wallets.0.iter().zip(self.config.wallet_labels.iter()) {
+            let label_suffix = match label.to_string() {
+                x if x.is_empty() => x,
+                x => format!(" | {x}"),
+            };
+            if source && !pubkey && !keypair {
+                println!("{} | {}{label_suffix}", kp.pubkey(), label.origin)
+            } else if pubkey == keypair {
+                println!("{} | {} | {}{label_suffix}", kp.pubkey(), kp, label.origin)
             } else {
                 match pubkey {
                     true => println!("{}", kp.pubkey()),
@@ -129,199 +191,747 @@ impl CmdHandlers {
         Ok(())
     }
 
-    pub(crate) async fn save_wallets_to(&self, save_to: &Path) -> MainResult<()> {
-        wallet::save_wallets_to(self.config.wallets.clone(), save_to).await.context(WalletSnafu)
+    pub(crate) async fn save_wallets_to(&self, save_to: &Path, options: SaveOptions) -> MainResult<()> {
+        let names = self.config.wallet_labels.iter().map(|label| label.name.clone()).collect::<Vec<_>>();
+        wallet::save_wallets_to(self.config.wallets.clone(), &names, save_to, &options).await.context(WalletSnafu)
+    }
+
+    pub(crate) fn import_wallets(&self, config_path: &Path, path: &Path, link: bool) -> MainResult<()> {
+        let mut known = self.config.wallets.0.iter()
+            .map(|kp| kp.pubkey().0)
+            .collect::<std::collections::HashSet<_>>();
+        let mut imported = Vec::new();
+        for file in wallet::find_keypair_files(path).context(WalletSnafu)? {
+            let kp = match wallet::read_keypair_file(&file) {
+                Ok(kp) => kp,
+                Err(e) => { eprintln!("skipped: {e}"); continue }
+            };
+            if !known.insert(kp.pubkey().0) {
+                eprintln!("skipped: {} ({}) is already in the config", kp.pubkey(), file.to_string_lossy());
+                continue
+            }
+            eprintln!("{}. {} <- {}", self.config.wallet_sources.len() + imported.len(), kp.pubkey(), file.to_string_lossy());
+            imported.push(match link {
+                // keypair file paths in the config are relative to its directory
+                true => config::KeypairSource::File(file).with_absolute_path(),
+                false => config::KeypairSource::Inline(kp),
+            });
+        }
+        if imported.is_empty() {
+            eprintln!("Nothing to import");
+            return Ok(())
+        }
+        let mut config_file = ConfigFile::read(config_path).context(ConfigFileSnafu)?;
+        config_file.append_to_sequence("wallets", &imported.iter().map(|source| source.to_yaml_item()).collect::<Vec<_>>())
+            .context(ConfigFileSnafu)?;
+        let backup_path = config_file.save_with_backup().context(ConfigFileSnafu)?;
+        eprintln!("{} wallets imported, the original config is saved to {}", imported.len(), backup_path.to_string_lossy());
+        if self.config.wallets.0.len() > self.config.wallet_sources.len() {
+            eprintln!("Note: unreferenced keystore wallets follow the config wallets, their indices have changed");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn remove_wallet(&self, config_path: &Path, wallet: AddressRef) -> MainResult<()> {
+        let index = self.config.wallet_index(&wallet).context(ConfigSnafu)?;
+        let in_keystore = self.config.wallet_sources.get(index)
+            .is_none_or(|entry| matches!(entry.source, config::KeypairSource::Keystore(_)));
+        if in_keystore {
+            return Err(MainError::CommandFailed {
+                msg: format!("wallet {wallet} is stored in the keystore, decrypt it first")
+            })
+        }
+        let mut config_file = ConfigFile::read(config_path).context(ConfigFileSnafu)?;
+        // the entry isn't printed, it may hold the secret key (the backup keeps it)
+        config_file.remove_sequence_item("wallets", index)
+            .ok_or_else(|| MainError::CommandFailed {
+                msg: "can't find the wallet in the config file (flow style \"wallets\" lists are not supported)".into()
+            })?;
+        let backup_path = config_file.save_with_backup().context(ConfigFileSnafu)?;
+        eprintln!(
+            "{index}. {} removed, the original config is saved to {}",
+            self.config.display_address(&self.config.wallets.0[index].0.pubkey()), backup_path.to_string_lossy(),
+        );
+        let references = self.removed_wallet_references(config_path, index)?;
+        if !references.is_empty() {
+            eprintln!("Fix the test transfers referring to the removed wallet or the following ones by index:");
+        }
+        let file_name = config_path.to_string_lossy();
+        for (path, msg) in references {
+            match config_file.line_of(&path) {
+                Some(line) => eprintln!("{file_name}:{line}: {path}: {msg}"),
+                None => eprintln!("{file_name}: {path}: {msg}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// YAML paths (of every profile of the config file) of the test transfer parties referring to the removed wallet
+    /// or to the wallets after it by index, with the descriptions of the problems.
+    fn removed_wallet_references(&self, config_path: &Path, removed: usize) -> MainResult<Vec<(String, String)>> {
+        let mut references = Vec::new();
+        for config in Config::read(config_path).context(ConfigSnafu)?.with_each_profile() {
+            let test_path = config.section_path("test");
+            for (kind, cases) in [("sols", &config.test.transfers.sols), ("tokens", &config.test.transfers.tokens)] {
+                for (i, case) in cases.iter().enumerate() {
+                    for (field, reference) in [("from", &case.from), ("to", &case.to)] {
+                        let msg = match (reference, self.config.wallet_index(reference)) {
+                            (_, Ok(index)) if index == removed => format!("wallet {reference} is removed"),
+                            (AddressRef::Index(index), _) if *index > removed => {
+                                format!("wallet index {index} is {} now", index - 1)
+                            }
+                            _ => continue,
+                        };
+                        references.push((format!("{test_path}.transfers.{kind}[{i}].{field}"), msg));
+                    }
+                }
+            }
+        }
+        // profiles without their own test section repeat the top level references
+        let mut seen = std::collections::HashSet::new();
+        references.retain(|(path, _)| seen.insert(path.clone()));
+        Ok(references)
+    }
+
+    pub(crate) fn encrypt_wallets(&self, config_path: &Path, keystore_path: PathBuf) -> MainResult<()> {
+        if let Some(path) = &self.config.keystore {
+            return Err(MainError::CommandFailed {
+                msg: format!("the config already has a keystore {}, decrypt it first", path.to_string_lossy())
+            })
+        }
+        if keystore_path.exists() {
+            return Err(MainError::CommandFailed {
+                msg: format!("keystore file {} already exists", keystore_path.to_string_lossy())
+            })
+        }
+        // inline keypairs are replaced with `keystore:N` references in place, so wallet indices stay the same
+        let mut wallets = Vec::new();
+        let items = self.config.wallet_sources.iter()
+            .map(|entry| match &entry.source {
+                config::KeypairSource::Inline(_) => {
+                    wallets.push(config::WalletEntry::from(entry.source.clone()));
+                    config::WalletEntry { source: config::KeypairSource::Keystore(wallets.len() - 1), ..entry.clone() }
+                }
+                _ => entry.clone(),
+            }.to_yaml_item())
+            .collect::<Vec<_>>();
+        let password = keystore::read_password(true).context(KeystoreSnafu)?;
+        keystore::Keystore::encrypt(&wallets, &password).context(KeystoreSnafu)?
+            .write(&keystore_path).context(KeystoreSnafu)?;
+
+        // the config refers to the keystore relative to its own directory
+        let absolute = |path: &Path| std::path::absolute(path).map_err(|e| MainError::CommandFailed {
+            msg: format!("can't resolve path {}: {e}", path.to_string_lossy()),
+        });
+        let keystore_path = absolute(&keystore_path)?;
+        // the parent of a bare file name is empty
+        let config_dir = absolute(config_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")))?;
+        let config_keystore_path = keystore_path.strip_prefix(&config_dir).unwrap_or(&keystore_path);
+        let mut config_file = ConfigFile::read(config_path).context(ConfigFileSnafu)?;
+        config_file.set_sequence("wallets", &items);
+        config_file.set_scalar("keystore", &config_keystore_path.to_string_lossy());
+        // a backup would keep the plaintext keypairs next to the keystore
+        config_file.save().context(ConfigFileSnafu)?;
+        eprintln!(
+            "{} wallets encrypted into {}, the config refers to it as {}",
+            wallets.len(), keystore_path.to_string_lossy(), config_keystore_path.to_string_lossy(),
+        );
+        Ok(())
+    }
+
+    pub(crate) fn decrypt_wallets(&self, config_path: &Path) -> MainResult<()> {
+        let Some(keystore_path) = &self.config.keystore else {
+            return Err(MainError::CommandFailed { msg: "the config has no keystore".into() })
+        };
+        // keystore references are replaced in place, unreferenced keystore wallets are appended
+        let inline = |i: usize| {
+            let label = &self.config.wallet_labels[i];
+            config::WalletEntry {
+                name: label.name.clone(),
+                tags: label.tags.clone(),
+                source: config::KeypairSource::Inline(self.config.wallets.0[i].clone()),
+            }
+        };
+        let mut decrypted = 0;
+        let entries = (0..self.config.wallets.0.len())
+            .map(|i| match self.config.wallet_sources.get(i) {
+                Some(entry) if !matches!(entry.source, config::KeypairSource::Keystore(_)) => entry.clone(),
+                _ => { decrypted += 1; inline(i) }
+            })
+            .collect::<Vec<_>>();
+        let mut config_file = ConfigFile::read(config_path).context(ConfigFileSnafu)?;
+        config_file.set_sequence("wallets", &entries.iter().map(|entry| entry.to_yaml_item()).collect::<Vec<_>>());
+        config_file.remove("keystore");
+        let backup_path = config_file.save_with_backup().context(ConfigFileSnafu)?;
+        eprintln!(
+            "{decrypted} wallets decrypted into the config, the original config is saved to {}; keystore {} can be removed",
+            backup_path.to_string_lossy(), config_path.parent().unwrap_or(Path::new("")).join(keystore_path).to_string_lossy(),
+        );
+        Ok(())
+    }
+
+    pub(crate) fn sign_message(&self, signer: AddressRef, message: MessageArgs) -> MainResult<()> {
+        let wallet = &self.config.wallets.0[self.config.wallet_index(&signer).context(ConfigSnafu)?];
+        let message = read_message(&message)?;
+        let signature = wallet::sign_message(wallet, &message).context(WalletSnafu)?;
+        println!("{signature}");
+        Ok(())
     }
 
     pub(crate) async fn deploy_token(&self) -> MainResult<()> {
         let client = self.connect();
         let(deploy_tx, _token) = token::deploy(
             client.clone(),
+            self.blockhashes(),
             self.token_mint.clone(),
             self.token_owner.clone(),
         ).await.context(TokenSnafu)?;
-        client.poll_for_signature_confirmation(&deploy_tx, 1).await.context(RpcSnafu)?;
+        self.confirmer().confirm(&deploy_tx, self.config.rpc.wait_for).await.context(ConfirmSnafu)?;
         Ok(())
     }
 
-    pub(crate) async fn mint_to(&self, holder: PubkeySerde, amount: f64) -> MainResult<()> {
+    pub(crate) async fn mint_to(&self, holder: AddressRef, amount: f64) -> MainResult<()> {
+        let holder = self.config.address(&holder).context(ConfigSnafu)?;
         let amount = f64::floor(amount * (10f64.powf(token::Token::DECIMALS as f64))) as u64;
-        let client = self.connect();
-        let token = token::Token::new(client.clone(), self.token_mint.pubkey().clone(), self.token_owner.clone());
-        let minting_tx = token.mint_to(&holder.0, amount).await.context(TokenSnafu)?;
-        client.poll_for_signature_confirmation(&minting_tx, 1).await.context(RpcSnafu)?;
+        let token = self.token();
+        let minting_tx = token.mint_to(&holder, amount).await.context(TokenSnafu)?;
+        self.confirmer().confirm(&minting_tx, self.config.rpc.wait_for).await.context(ConfirmSnafu)?;
         Ok(())
     }
 
     pub(crate) async fn token_balances(&self) -> MainResult<()> {
-        let rpc_client = self.connect();
-        let token = token::Token::new(
-            rpc_client,
-            self.config.token.mint.0.pubkey(),
-            Arc::new(self.config.token.owner.clone().0)
-        );
+        let token = self.token();
 
         let mut handles= Vec::new();
         // let mut results: Vec<u64> = Vec::new();
         for (i, KeypairSerde(wallet)) in self.config.wallets.0.iter().enumerate() {
             let (pk, token) = (wallet.pubkey(), token.clone());
+            let address = self.config.display_address(&pk);
             handles.push(async move {
-                (i, pk, token.get_associated_token_account_balance(&pk).await)
+                (i, address, token.get_associated_token_account_balance(&pk).await)
             });
         }
         let results = join_all(handles).await;
 
-        for (i, pk, res) in results {
+        for (i, address, res) in results {
             match res {
-                Ok(balance) => println!("{i}. {pk}: {}", token::Token::subunits_to_coins(balance)),
-                Err(err) => println!("{i}. {pk}: error: {err}"),
+                Ok(balance) => println!("{i}. {address}: {}", token::Token::subunits_to_coins(balance)),
+                Err(err) => println!("{i}. {address}: error: {err}"),
             }
         }
         Ok(())
     }
 
-    pub(crate) async fn test_batched_sols_transfer(&self) -> MainResult<()> {
+    /// Sender keypair (must be an owned wallet) and receiver address of a test transfer.
+    fn transfer_parties(&self, from: &AddressRef, to: &AddressRef) -> config::ConfigResult<(KeypairSerde, Pubkey)> {
+        let from_kp = self.config.wallets.0[self.config.wallet_index(from)?].clone();
+        Ok((from_kp, self.config.address(to)?))
+    }
+
+    pub(crate) async fn test_batched_sols_transfer(
+        &self, run_id: Option<String>, send_via: SendVia, concurrency: usize, timeout: Option<Duration>,
+    ) -> MainResult<()> {
         let wallets_count = self.config.wallets.0.len();
         if wallets_count < 1 { return Ok(()) }
+        let run_id = run_id.unwrap_or_else(new_run_id);
+        println!("run id: {run_id}");
         let client = self.connect();
-        let mut wrk = worker::Worker::new();
-        for (i, TestTransferConfig { from, to, amount }) in self.config.test.transfers.sols.clone().into_iter().enumerate() {
-            if from >= wallets_count {
-                eprintln!("invalid sender wallet index {from}");
-                continue
-            }
-            if to >= wallets_count {
-                eprintln!("invalid receiver wallet index {to}");
-                continue
-            }
+        let confirmer = self.confirmer();
+        let tx_sender = self.transaction_sender(client.clone(), confirmer.clone(), send_via).await?;
+        let times = Arc::new(ConfirmationTimes::default());
+        // transfers aren't retried by the worker, a failed one might have been sent already,
+        // expired blockhashes are handled by the sender
+        let mut wrk = worker::Worker::with_limit(concurrency);
+        if let Some(timeout) = timeout {
+            wrk = wrk.with_timeout(timeout);
+        }
+        let mut transfers = Vec::new();
+        for (i, case) in self.config.test.transfers.sols.clone().into_iter().enumerate() {
+            let memo = case.render_memo(&run_id, i);
+            let TestTransferConfig { from, to, amount, .. } = case;
+            let (from_kp, to_pk) = match self.transfer_parties(&from, &to) {
+                Ok(x) => x, Err(e) => { eprintln!("{i}. {e}"); continue }
+            };
             let lamports = sol_to_lamports(amount);
             let amount = lamports_to_sol(lamports);
-            let from_kp = self.config.wallets.0[from].clone();
-            let to_kp = self.config.wallets.0[to].clone();
-            let client = client.clone();
-            wrk.push(async move {
-                let from_pk = from_kp.pubkey();
-                let to_pk = to_kp.pubkey();
-                let print_error = |e: &dyn std::error::Error| {
-                    eprintln!("{i}. transfer {amount} SOL {from_pk} -> {to_pk} error: {e}")
-                };
-                let sender_balance = match client.get_balance(&from_pk.0).await {
-                    Ok(x) => x, Err(ref e) => return print_error(e),
-                };
-                if lamports > sender_balance {
-                    eprintln!(
-                        "{i}. transfer {from_pk} -> {to_pk} error: insufficient balance {} < {}",
-                        lamports_to_sol(sender_balance), lamports_to_sol(lamports)
-                    );
-                }
-                let recent_blockhash = match client.get_latest_blockhash().await {
-                    Ok(x) => x, Err(ref e) => return print_error(e),
-                };
-                let transfer_tx = match wallet::transfer_sol(
-                    client.as_ref(), &from_kp.0, &to_pk.0, lamports,
-                    Some(recent_blockhash),
-                    Some(&from_kp.0),
-                    Some("Test transfer"),
-                ).await { Ok(x) => x, Err(ref e) => return print_error(e)};
-                println!("{i}. transferred {amount:.2} from {from_pk} to {to_pk}\n    tx: {transfer_tx}");
-                let start_time = Instant::now();
-                match client.poll_for_signature_with_commitment(&transfer_tx, CommitmentConfig::confirmed()).await {
-                    Ok(x) => x, Err(ref e) => return print_error(e),
+            let (from_label, to_label) = (self.config.display_address(&from_kp.0.pubkey()), self.config.display_address(&to_pk));
+            transfers.push((i, format!("{amount} SOL {from_label} -> {to_label}")));
+            let route = send_via.route(i);
+            let description = format!("{amount:.2} from {from_label} to {to_label} via {route}");
+            let parties = format!("{from_label} -> {to_label}");
+            let (client, confirmer, tx_sender, times) = (client.clone(), confirmer.clone(), tx_sender.clone(), times.clone());
+            let confirmation_levels = self.config.rpc.confirmation_levels();
+            wrk.push(move || {
+                let (from_kp, memo, description, parties) = (from_kp.clone(), memo.clone(), description.clone(), parties.clone());
+                let (client, confirmer, tx_sender, times) = (client.clone(), confirmer.clone(), tx_sender.clone(), times.clone());
+                let confirmation_levels = confirmation_levels.clone();
+                async move {
+                    let from_pk = from_kp.pubkey();
+                    let sender_balance = client.get_balance(&from_pk.0).await.context(RpcSnafu)?;
+                    if lamports > sender_balance {
+                        eprintln!(
+                            "{i}. transfer {parties} error: insufficient balance {} < {}",
+                            lamports_to_sol(sender_balance), lamports_to_sol(lamports)
+                        );
+                    }
+                    let (first_level, next_levels) = confirmation_levels.split_first().expect("at least one level");
+                    let start_time = Instant::now();
+                    let transfer_tx = tx_sender.send_with_retry(
+                        |recent_blockhash| wallet::transfer_sol_tx(
+                            &from_kp.0, &to_pk, lamports, recent_blockhash, Some(&from_kp.0), Some(&memo),
+                        ),
+                        route,
+                        *first_level,
+                        |attempt, tx| print_attempt(i, attempt, tx, || description.clone()),
+                    ).await.context(SendSnafu)?;
+                    let spent_time = start_time.elapsed();
+                    println!("{i}. tx: {transfer_tx} {first_level} in {spent_time:?}");
+                    times.record(route, *first_level, spent_time);
+                    for level in next_levels {
+                        let start_time = Instant::now();
+                        confirmer.confirm(&transfer_tx, *level).await.context(ConfirmSnafu)?;
+                        let spent_time = start_time.elapsed();
+                        println!("{i}. tx: {transfer_tx} {level} in {spent_time:?}");
+                        times.record(route, *level, spent_time);
+                    }
+                    Ok(transfer_tx)
                 }
-                let spent_time = start_time.elapsed();
-                println!("{i}. tx: {transfer_tx} confirmed in {spent_time:?}");
-                let start_time = Instant::now();
-                match client.poll_for_signature_with_commitment(&transfer_tx, CommitmentConfig::finalized()).await {
-                    Ok(x) => x, Err(ref e) => return print_error(e),
-                }
-                let spent_time = start_time.elapsed();
-                println!("{i}. tx: {transfer_tx} finalized in {spent_time:?}");
             });
         }
-        // if there is a lot of tasks, it would be preferred to use `run` instead of `run_all_joined`
-        // because there is a risk to reach some OS limitations on a huge amount of simultaneous connections,
+        // the limit keeps a huge amount of simultaneous connections from reaching OS limitations,
         // especially if validator works on the same machine (I've tested).
-        // In other cases `run_all_joined` is possibly faster.
-        wrk.run_all_joined().await;
+        let reports = wrk.run().await;
+        print_transfer_reports(&transfers, &reports);
+        times.print_summary(&self.config.rpc.confirmation_levels());
         Ok(())
     }
 
-    pub(crate) async fn test_batched_tokens_transfer(&self) -> MainResult<()> {
+    pub(crate) async fn test_batched_tokens_transfer(
+        &self, run_id: Option<String>, send_via: SendVia, concurrency: usize, timeout: Option<Duration>,
+    ) -> MainResult<()> {
         let wallets_count = self.config.wallets.0.len();
         if wallets_count < 1 { return Ok(()) }
+        let run_id = run_id.unwrap_or_else(new_run_id);
+        println!("run id: {run_id}");
         let rpc_client = self.connect();
-        let token = token::Token::new(
-            rpc_client.clone(),
-            self.config.token.mint.pubkey().clone().0,
-            Arc::new(self.config.token.owner.clone().0)
-        );
+        let token = self.token();
+        let confirmer = self.confirmer();
+        let tx_sender = self.transaction_sender(rpc_client.clone(), confirmer.clone(), send_via).await?;
+        let times = Arc::new(ConfirmationTimes::default());
 
-        let mut wrk = worker::Worker::new();
-        for (i, TestTransferConfig { from, to, amount }) in self.config.test.transfers.tokens.clone().into_iter().enumerate() {
-            let token = token.clone();
-            if from >= wallets_count {
-                eprintln!("invalid sender wallet index {from}");
-                continue
-            }
-            if to >= wallets_count {
-                eprintln!("invalid receiver wallet index {to}");
-                continue
-            }
+        let mut wrk = worker::Worker::with_limit(concurrency);
+        if let Some(timeout) = timeout {
+            wrk = wrk.with_timeout(timeout);
+        }
+        let mut transfers = Vec::new();
+        for (i, case) in self.config.test.transfers.tokens.clone().into_iter().enumerate() {
+            let memo = case.render_memo(&run_id, i);
+            let TestTransferConfig { from, to, amount, .. } = case;
+            let (from_kp, to_pk) = match self.transfer_parties(&from, &to) {
+                Ok(x) => x, Err(e) => { eprintln!("{i}. {e}"); continue }
+            };
             let subunits = token::Token::coins_to_subunits(amount);
             let amount = token::Token::subunits_to_coins(subunits);
-            let from_kp = self.config.wallets.0[from].clone();
-            let to_kp = self.config.wallets.0[to].clone();
-            let rpc_client = rpc_client.clone();
-            wrk.push(async move {
-                let from_pk = from_kp.pubkey();
-                let to_pk = to_kp.pubkey();
-                let print_error = |e: &dyn std::error::Error| {
-                    eprintln!("{i}. transfer {amount} Tokens {from_pk} -> {to_pk} error: {e}")
-                };
-                let sender_balance = match rpc_client.get_balance(&from_pk.0).await {
-                    Ok(x) => x, Err(ref e) => return print_error(e),
-                };
-                if subunits > sender_balance {
-                    eprintln!(
-                        "{i}. transfer {from_pk} -> {to_pk} error: insufficient balance {} < {}",
-                        lamports_to_sol(sender_balance), lamports_to_sol(subunits)
-                    );
-                }
+            let (from_label, to_label) = (self.config.display_address(&from_kp.0.pubkey()), self.config.display_address(&to_pk));
+            transfers.push((i, format!("{amount} Tokens {from_label} -> {to_label}")));
+            let route = send_via.route(i);
+            let description = format!("{amount:.2} from {from_label} to {to_label} via {route}");
+            let parties = format!("{from_label} -> {to_label}");
+            let (token, rpc_client, confirmer, tx_sender, times) = (token.clone(), rpc_client.clone(), confirmer.clone(), tx_sender.clone(), times.clone());
+            let confirmation_levels = self.config.rpc.confirmation_levels();
+            wrk.push(move || {
+                let (from_kp, memo, description, parties) = (from_kp.clone(), memo.clone(), description.clone(), parties.clone());
+                let (token, rpc_client, confirmer, tx_sender, times) = (token.clone(), rpc_client.clone(), confirmer.clone(), tx_sender.clone(), times.clone());
+                let confirmation_levels = confirmation_levels.clone();
+                async move {
+                    let from_pk = from_kp.pubkey();
+                    let sender_balance = rpc_client.get_balance(&from_pk.0).await.context(RpcSnafu)?;
+                    if subunits > sender_balance {
+                        eprintln!(
+                            "{i}. transfer {parties} error: insufficient balance {} < {}",
+                            lamports_to_sol(sender_balance), lamports_to_sol(subunits)
+                        );
+                    }
 
-                println!("{i}. transferring {amount:.2} from {from_pk} to {to_pk}...");
-                let transfer_tx = match token.transfer(&from_kp.0, &to_pk.0, subunits).await {
-                    Ok(x) => x, Err(ref e) => return print_error(e)
-                };
-                println!("{i}. transferred {amount:.2} from {from_pk} to {to_pk}\n    tx: {transfer_tx}");
-                let start_time = Instant::now();
-                match rpc_client.poll_for_signature_with_commitment(&transfer_tx, CommitmentConfig::confirmed()).await {
-                    Ok(x) => x, Err(ref e) => return print_error(e),
-                }
-                let spent_time = start_time.elapsed();
-                println!("{i}. tx: {transfer_tx} confirmed in {spent_time:?}");
-                let start_time = Instant::now();
-                match rpc_client.poll_for_signature_with_commitment(&transfer_tx, CommitmentConfig::finalized()).await {
-                    Ok(x) => x, Err(ref e) => return print_error(e),
+                    let balance = token.get_associated_token_account_balance(&from_pk.0).await.context(TokenSnafu)?;
+                    if balance < subunits {
+                        return Err(token::TokenError::InsufficientBalance).context(TokenSnafu)
+                    }
+                    let instructions = token.transfer_instructions(&from_pk.0, &to_pk, subunits, Some(memo)).context(TokenSnafu)?;
+                    let (first_level, next_levels) = confirmation_levels.split_first().expect("at least one level");
+                    let start_time = Instant::now();
+                    let transfer_tx = tx_sender.send_with_retry(
+                        |recent_blockhash| token.signed_tx(&instructions, &from_kp.0, recent_blockhash),
+                        route,
+                        *first_level,
+                        |attempt, tx| print_attempt(i, attempt, tx, || description.clone()),
+                    ).await.context(SendSnafu)?;
+                    let spent_time = start_time.elapsed();
+                    println!("{i}. tx: {transfer_tx} {first_level} in {spent_time:?}");
+                    times.record(route, *first_level, spent_time);
+                    for level in next_levels {
+                        let start_time = Instant::now();
+                        confirmer.confirm(&transfer_tx, *level).await.context(ConfirmSnafu)?;
+                        let spent_time = start_time.elapsed();
+                        println!("{i}. tx: {transfer_tx} {level} in {spent_time:?}");
+                        times.record(route, *level, spent_time);
+                    }
+                    Ok(transfer_tx)
                 }
-                let spent_time = start_time.elapsed();
-                println!("{i}. tx: {transfer_tx} finalized in {spent_time:?}");
             });
         }
         // There is no possibility to run it in multithreaded mode,
         // because SPL token client is not Sendable (impl Send).
-        // But even a single-threaded performance is enough to send transactions in simultaneous batches.
+        // But even a single-threaded performance is enough to keep `concurrency` transactions in flight.
         // I can make it multithreaded, but it would take some time to rework SPL Token client.
-        wrk.run_single_threaded(Some(32)).await;
+        let reports = wrk.run_single_threaded().await;
+        print_transfer_reports(&transfers, &reports);
+        times.print_summary(&self.config.rpc.confirmation_levels());
         Ok(())
     }
 }
 
-pub(crate) async fn generate_wallets(count: usize, save_to: Option<PathBuf>) -> MainResult<()> {
+/// Prints every problem of the config file (all the profiles, without overrides) as `file:line: yaml.path: message`.
+pub(crate) async fn validate_config(config_path: &Path, offline: bool) -> MainResult<()> {
+    let mut issues = Config::validate_file(config_path).context(ConfigSnafu)?;
+    if !offline {
+        let mut checked = std::collections::HashSet::new();
+        for config in Config::read(config_path).context(ConfigSnafu)?.with_each_profile() {
+            let rpc_path = config.section_path("rpc");
+            for (i, endpoint) in config.rpc.all_endpoints().into_iter().enumerate() {
+                if !checked.insert(endpoint.uri.0.clone()) {
+                    continue
+                }
+                let client = RpcClient::new_with_timeout(endpoint.uri.0.to_string(), Duration::from_secs(10));
+                if let Err(e) = client.get_version().await {
+                    let path = match i {
+                        0 => format!("{rpc_path}.uri"),
+                        _ => format!("{rpc_path}.endpoints[{}].uri", i - 1),
+                    };
+                    issues.push(config::ConfigIssue {
+                        path,
+                        msg: format!("RPC endpoint {} is not reachable: {e}", endpoint.uri.0),
+                    });
+                }
+            }
+        }
+    }
+    let config_file = ConfigFile::read(config_path).context(ConfigFileSnafu)?;
+    let file_name = config_path.to_string_lossy();
+    for config::ConfigIssue { path, msg } in issues.iter() {
+        match config_file.line_of(path) {
+            Some(line) => println!("{file_name}:{line}: {path}: {msg}"),
+            None => println!("{file_name}: {path}: {msg}"),
+        }
+    }
+    match issues.len() {
+        0 => {
+            eprintln!("config is valid");
+            Ok(())
+        }
+        count => Err(MainError::CommandFailed { msg: format!("{count} config problem(s) found") }),
+    }
+}
+
+pub(crate) fn verify_message(pubkey: PubkeySerde, signature: Signature, message: MessageArgs) -> MainResult<()> {
+    let message = read_message(&message)?;
+    match wallet::verify_message(&pubkey, &signature, &message).context(WalletSnafu)? {
+        true => { println!("OK"); Ok(()) }
+        false => Err(wallet::WalletError::InvalidMessageSignature).context(WalletSnafu),
+    }
+}
+
+fn read_message(message: &MessageArgs) -> MainResult<Vec<u8>> {
+    message.read()
+        .context(wallet::ReadMessageFileSnafu {
+            path: message.file.as_ref().map(|p| p.to_string_lossy().to_string()).unwrap_or_default()
+        })
+        .context(WalletSnafu)
+}
+
+/// Prints the signature of a test transfer attempt, following ones are made after the blockhash expiration.
+fn print_attempt(i: usize, attempt: u32, tx: &Signature, transfer: impl FnOnce() -> String) {
+    match attempt {
+        1 => println!("{i}. transferring {}\n    tx: {tx}", transfer()),
+        _ => println!("{i}. blockhash expired, attempt {attempt}\n    tx: {tx}"),
+    }
+}
+
+/// Prints the failures of test transfer jobs and the counts by outcome,
+/// `transfers` are the numbers and descriptions of the pushed jobs.
+fn print_transfer_reports(transfers: &[(usize, String)], reports: &[worker::JobReport<Signature, MainError>]) {
+    for ((i, transfer), report) in transfers.iter().zip(reports) {
+        match &report.outcome {
+            worker::JobOutcome::Ok(_) => {}
+            worker::JobOutcome::Err(e) => eprintln!("{i}. transfer {transfer} error: {e}"),
+            worker::JobOutcome::Panicked(message) => eprintln!("{i}. transfer {transfer} panicked: {message}"),
+            worker::JobOutcome::TimedOut => eprintln!("{i}. transfer {transfer} timed out after {:?}", report.duration),
+        }
+    }
+    if !reports.is_empty() {
+        println!("transfers: {}", worker::JobSummary::of(reports));
+    }
+}
+
+/// Confirmation times of test transfers by send route, they're summarized after a run to compare the routes.
+/// The first level is timed from the first send of a transfer, the next ones from reaching the previous level.
+#[derive(Default)]
+struct ConfirmationTimes(std::sync::Mutex<Vec<(Route, CommitmentLevel, Duration)>>);
+
+impl ConfirmationTimes {
+    fn record(&self, route: Route, level: CommitmentLevel, time: Duration) {
+        self.0.lock().expect("not poisoned").push((route, level, time));
+    }
+
+    fn print_summary(&self, levels: &[CommitmentLevel]) {
+        let records = self.0.lock().expect("not poisoned");
+        if records.is_empty() {
+            return
+        }
+        println!("confirmation times:");
+        for route in [Route::Rpc, Route::Tpu] {
+            for level in levels {
+                let mut times = records.iter()
+                    .filter(|(r, l, _)| *r == route && l == level)
+                    .map(|(_, _, time)| *time)
+                    .collect::<Vec<_>>();
+                if times.is_empty() {
+                    continue
+                }
+                times.sort();
+                let mean = times.iter().sum::<Duration>() / times.len() as u32;
+                println!(
+                    "  {route} {level}: {} tx, min {:?}, median {:?}, mean {mean:?}, max {:?}",
+                    times.len(), times[0], times[times.len() / 2], times[times.len() - 1],
+                );
+            }
+        }
+    }
+}
+
+/// Run identifier to tie test transactions together (milliseconds since epoch, hex encoded).
+fn new_run_id() -> String {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    format!("{millis:x}")
+}
+
+pub(crate) async fn generate_wallets(count: usize, save_to: Option<PathBuf>, save_options: SaveOptions) -> MainResult<()> {
+    let wallets = config::generate_wallets(count).context(ConfigSnafu)?;
+    match save_to {
+        Some(save_path_buf) => {
+            wallet::save_wallets_to(wallets, &[], save_path_buf.as_path(), &save_options).await.context(WalletSnafu)
+        }
+        None => { wallets.print_yaml(); Ok(()) }
+    }
+}
+
+pub(crate) fn init_config(config_path: &Path, wallet_count: usize, cluster: &str, force: bool) -> MainResult<()> {
+    if config_path.exists() && !force {
+        return Err(MainError::CommandFailed {
+            msg: format!("{} already exists (use --force to overwrite)", config_path.to_string_lossy()),
+        })
+    }
+    let rpc_url = config::cluster_url(cluster).context(ConfigSnafu)?;
+    let yaml = config::starter_config_yaml(wallet_count, &rpc_url).context(ConfigSnafu)?;
+    wallet::write_secret_file_atomically(config_path, yaml.as_bytes())
+        .context(config_file::WriteFailedSnafu { path: config_path.to_string_lossy() })
+        .context(ConfigFileSnafu)?;
+    eprintln!("config with {wallet_count} wallets for {rpc_url} is written to {}", config_path.to_string_lossy());
+    Ok(())
+}
+
+pub(crate) fn generate_wallets_into_config(count: usize, config_path: &Path) -> MainResult<()> {
+    if let Some(keystore_path) = Config::read(config_path).context(ConfigSnafu)?.keystore {
+        // inline keypairs would be stored in plaintext next to the keystore
+        // and shift the indices of the unreferenced keystore wallets
+        return Err(MainError::CommandFailed {
+            msg: format!(
+                "the config has a keystore {}, decrypt it first (and encrypt the wallets back after appending)",
+                keystore_path.to_string_lossy(),
+            ),
+        })
+    }
     let wallets = config::generate_wallets(count).context(ConfigSnafu)?;
+    let mut config_file = ConfigFile::read(config_path).context(ConfigFileSnafu)?;
+    config_file.append_to_sequence("wallets", &wallets.0.iter().map(|kp| kp.to_string()).collect::<Vec<_>>())
+        .context(ConfigFileSnafu)?;
+    let backup_path = config_file.save_with_backup().context(ConfigFileSnafu)?;
+    for kp in wallets.0.iter() {
+        println!("{}", kp.pubkey());
+    }
+    eprintln!("{count} wallets appended to {}, the original config is saved to {}", config_path.to_string_lossy(), backup_path.to_string_lossy());
+    Ok(())
+}
+
+pub(crate) async fn grind_wallets(
+    pattern: wallet::VanityPattern,
+    count: usize,
+    save_to: Option<PathBuf>,
+    save_options: SaveOptions,
+) -> MainResult<()> {
+    let threads = std::thread::available_parallelism().map(|x| x.get()).unwrap_or(1);
+    eprintln!("Grinding {count} wallet(s) matching {pattern:?} using {threads} threads...");
+    let wallets = tokio::task::spawn_blocking(move || wallet::grind_wallets(&pattern, count, threads))
+        .await
+        .context(crate::TaskJoinSnafu)?;
+    match save_to {
+        Some(save_path_buf) => {
+            wallet::save_wallets_to(wallets, &[], save_path_buf.as_path(), &save_options).await.context(WalletSnafu)
+        }
+        None => { wallets.print_yaml(); Ok(()) }
+    }
+}
+
+pub(crate) async fn generate_mnemonic_wallets(
+    count: usize,
+    save_to: Option<PathBuf>,
+    save_options: SaveOptions,
+    word_count: usize,
+    passphrase: String,
+) -> MainResult<()> {
+    let seed = config::SeedConfig::generate(word_count, passphrase).context(ConfigSnafu)?;
+    let count = u32::try_from(count).ok()
+        .filter(|count| *count <= config::DERIVATION_INDEX_LIMIT)
+        .ok_or_else(|| MainError::CommandFailed {
+            msg: format!("wallet count {count} exceeds the derivation index range (max {})", config::DERIVATION_INDEX_LIMIT),
+        })?;
     match save_to {
         Some(save_path_buf) => {
-            wallet::save_wallets_to(wallets, save_path_buf.as_path()).await.context(WalletSnafu)
+            eprintln!("seed phrase: {}", seed.phrase);
+            let wallets = seed.derive_wallets(0..count).context(ConfigSnafu)?;
+            wallet::save_wallets_to(wallets, &[], save_path_buf.as_path(), &save_options).await.context(WalletSnafu)
+        }
+        None => {
+            let snippet = seed_config_snippet(seed, 0..count)
+                .and_then(|snippet| serde_yaml::to_string(&snippet))
+                .context(config::YamlSerializationFailedSnafu { path: "<stdout>" })
+                .context(ConfigSnafu)?;
+            println!("{snippet}");
+            Ok(())
+        }
+    }
+}
+
+pub(crate) async fn recover_wallets(
+    seed: config::SeedConfig,
+    start: u32,
+    count: u32,
+    save_to: Option<PathBuf>,
+    save_options: SaveOptions,
+) -> MainResult<()> {
+    let end = start.checked_add(count)
+        .filter(|end| *end <= config::DERIVATION_INDEX_LIMIT)
+        .ok_or_else(|| MainError::CommandFailed {
+            msg: format!(
+                "derivation indices {start}..{start}+{count} exceed the derivation index range (max {})",
+                config::DERIVATION_INDEX_LIMIT,
+            ),
+        })?;
+    let wallets = seed.derive_wallets(start..end).context(ConfigSnafu)?;
+    match save_to {
+        Some(save_path_buf) => {
+            wallet::save_wallets_to(wallets, &[], save_path_buf.as_path(), &save_options).await.context(WalletSnafu)
         }
         None => { wallets.print_yaml(); Ok(()) }
     }
 }
+
+/// Config fragment (`seed` + `wallets` sections) for the derived wallets.
+fn seed_config_snippet(seed: config::SeedConfig, indices: std::ops::Range<u32>) -> Result<serde_yaml::Mapping, serde_yaml::Error> {
+    let mut snippet = serde_yaml::Mapping::new();
+    snippet.insert("seed".into(), serde_yaml::to_value(seed)?);
+    snippet.insert("wallets".into(), indices
+        .map(|derivation_index| serde_yaml::to_value(config::KeypairSource::Derived { derivation_index }))
+        .collect::<Result<_, _>>()?);
+    Ok(snippet)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser as _;
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("cmd-test-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// The config as the commands get it, no overrides.
+    async fn load(config_path: &Path) -> Config {
+        let cli = crate::cli::Cli::try_parse_from(["test-task", "balances"]).unwrap();
+        Config::load(config_path, None, &cli.overrides).await.unwrap()
+    }
+
+    /// Config with fresh token keypairs, no test transfers and the given wallet sections.
+    fn config_yaml(wallets: &str) -> String {
+        format!("\
+rpc:
+  uri: http://localhost:8899
+token: {{ owner: {owner}, mint: {owner} }}
+test: {{ mint: 11111111111111111111111111111111, transfers: {{ sols: [], tokens: [] }} }}
+address_book: {{}}
+{wallets}",
+            owner = Keypair::new().to_base58_string(),
+        )
+    }
+
+    fn write_keypair_file(path: &Path, kp: &Keypair) {
+        std::fs::write(path, serde_json::to_string(&kp.to_bytes().to_vec()).unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn import_into_flow_sequence() {
+        let dir = TempDir::new("import-flow");
+        let [alice, bob, imported, other] = [(); 4].map(|_| Keypair::new());
+        let keys_dir = dir.0.join("keys");
+        std::fs::create_dir(&keys_dir).unwrap();
+        write_keypair_file(&keys_dir.join("a.json"), &imported);
+        write_keypair_file(&keys_dir.join("b.json"), &bob);
+        write_keypair_file(&keys_dir.join("c.json"), &other);
+        let config_path = dir.0.join("config.yaml");
+        std::fs::write(&config_path, config_yaml(&format!(
+            "wallets: [{{ name: alice, tags: [a, b], keypair: {} }}, {}]\n",
+            alice.to_base58_string(), bob.to_base58_string(),
+        ))).unwrap();
+
+        CmdHandlers::new(load(&config_path).await).import_wallets(&config_path, &keys_dir, false).unwrap();
+
+        let config = load(&config_path).await;
+        let pubkeys = config.wallets.0.iter().map(|kp| kp.pubkey().0).collect::<Vec<_>>();
+        // the known wallet file is skipped
+        assert_eq!(pubkeys, [alice.pubkey(), bob.pubkey(), imported.pubkey(), other.pubkey()]);
+        assert_eq!(config.wallet_labels[0].name.as_deref(), Some("alice"));
+        assert_eq!(config.wallet_labels[0].tags, ["a", "b"]);
+        assert!(dir.0.join("config.yaml.bak").exists());
+    }
+
+    #[tokio::test]
+    async fn seed_snippet_round_trip() {
+        let dir = TempDir::new("seed-snippet");
+        let seed = config::SeedConfig::generate(12, "passphrase".into()).unwrap();
+        let snippet = seed_config_snippet(seed.clone(), 3..5).unwrap();
+        let config_path = dir.0.join("config.yaml");
+        std::fs::write(&config_path, config_yaml(&serde_yaml::to_string(&snippet).unwrap())).unwrap();
+
+        let config = load(&config_path).await;
+        let seed = seed.to_seed().unwrap();
+        let expected = (3..5).map(|i| config::derive_keypair(&seed, i).unwrap().pubkey().0).collect::<Vec<_>>();
+        assert_eq!(config.wallets.0.iter().map(|kp| kp.pubkey().0).collect::<Vec<_>>(), expected);
+        assert_eq!(config.wallet_labels[1].origin, "derived:m/44'/501'/4'/0'");
+    }
+}
@@ -1,68 +1,1157 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
-use snafu::{ResultExt, Snafu};
-use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use std::collections::BTreeMap;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize, Serializer, Deserializer, ser::SerializeMap as _};
+use schemars::{JsonSchema, gen::SchemaGenerator, schema::Schema};
+use bip39::{Language, Mnemonic, MnemonicType};
 use solana_sdk::{signature::{Keypair, Signer}, bs58};
+use solana_sdk::derivation_path::DerivationPath;
+use solana_sdk::signer::keypair::{generate_seed_from_seed_phrase_and_passphrase, keypair_from_seed_and_derivation_path};
+use solana_sdk::commitment_config::CommitmentLevel;
 use solana_sdk::pubkey::Pubkey;
-use crate::cli::Cli;
+use crate::cli::ConfigOverrides;
+use crate::keystore::{self, Keystore};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct Config {
     pub(crate) rpc: RpcConfig,
     pub(crate) token: TokenConfig,
     pub(crate) test: TestConfig,
+    /// BIP39 seed phrase for the derived wallets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) seed: Option<SeedConfig>,
+    #[serde(rename = "wallets")]
+    pub(crate) wallet_sources: Vec<WalletEntry>,
+    /// Encrypted keystore file (a relative path is taken from the config file directory), its wallets take the places of their `keystore:N` references in `wallets`,
+    /// unreferenced ones are appended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) keystore: Option<PathBuf>,
+    /// Labels of non-owned addresses
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) address_book: BTreeMap<String, PubkeySerde>,
+    /// Cluster specific `rpc`, `token` and `test` sections selected by `--profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) profiles: BTreeMap<String, ProfileConfig>,
+    /// Name of the selected profile, `None` for the top level values
+    #[serde(skip)]
+    pub(crate) active_profile: Option<String>,
+    /// Wallets resolved from `wallet_sources` and the keystore
+    #[serde(skip)]
     pub(crate) wallets: KeypairList,
+    /// Names, tags and origins of `wallets`
+    #[serde(skip)]
+    pub(crate) wallet_labels: Vec<WalletLabel>,
+    /// Keypairs resolved from the active `token` section
+    #[serde(skip)]
+    pub(crate) token_keypairs: Option<TokenKeypairs>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub(crate) struct TokenKeypairs {
+    pub(crate) owner: KeypairSerde,
+    pub(crate) mint: KeypairSerde,
+}
+
+/// Wallet config entry: a bare [`KeypairSource`] or a `{ name, tags, keypair }` mapping.
+#[derive(Debug, Clone)]
+pub(crate) struct WalletEntry {
+    pub(crate) name: Option<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) source: KeypairSource,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WalletLabel {
+    pub(crate) name: Option<String>,
+    pub(crate) tags: Vec<String>,
+    /// where the keypair comes from
+    pub(crate) origin: String,
+}
+
+/// Reference to an address: wallet index, wallet name, address book label or a public key.
+#[derive(Debug, Clone)]
+pub(crate) enum AddressRef {
+    Index(usize),
+    Pubkey(PubkeySerde),
+    Name(String),
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct SeedConfig {
+    pub(crate) phrase: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) passphrase: String,
+}
+
+/// Where a keypair comes from. In YAML it's a string (`env:VAR`, `file:path`, a path or inline base58)
+/// or a `{ derivation_index: N }` mapping.
+#[derive(Debug, Clone)]
+pub(crate) enum KeypairSource {
+    /// base58 encoded keypair
+    Inline(KeypairSerde),
+    /// solana-cli compatible keypair json file, a relative path is taken from the config file directory
+    File(PathBuf),
+    /// environment variable holding a base58 encoded keypair or a solana-cli compatible json array
+    Env(String),
+    /// keypair derived from the config seed phrase by `m/44'/501'/{derivation_index}'/0'` path
+    Derived { derivation_index: u32 },
+    /// wallet of the config keystore by its position, only in `wallets`
+    Keystore(usize),
+}
+
+/// Named profile, missing sections are taken from the top level of the config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct ProfileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rpc: Option<RpcConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) token: Option<TokenConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) test: Option<TestConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct RpcConfig {
     pub(crate) uri: Url,
-    // TODO: should I implement a rate-limit and a backoff on errors?
-    // pub(crate) rate_limit_per_sec: u16,
+    /// WebSocket endpoint of signature subscriptions, derived from `uri` if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ws_uri: Option<Url>,
+    /// Commitment level of RPC requests
+    #[serde(default)]
+    #[schemars(schema_with = "commitment_schema")]
+    pub(crate) commitment: CommitmentLevel,
+    /// Commitment level sent transactions are waited for, `confirmed` by default
+    #[serde(default = "RpcConfig::default_wait_for")]
+    #[schemars(schema_with = "commitment_schema")]
+    pub(crate) wait_for: CommitmentLevel,
+    /// Max RPC requests per second, unlimited if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rate_limit_per_sec: Option<u32>,
+    /// Max simultaneous RPC requests, unlimited if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_concurrent_requests: Option<usize>,
+    #[serde(default)]
+    pub(crate) retry: RetryConfig,
+    /// Max transaction signing attempts, a transaction is signed again with a fresh blockhash when the previous one expires
+    #[serde(default = "RpcConfig::default_max_send_attempts")]
+    pub(crate) max_send_attempts: u32,
+    /// Additional RPC endpoints, requests are spread across `uri` and them by weights
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) endpoints: Vec<EndpointConfig>,
+    #[serde(default)]
+    pub(crate) health_check: HealthCheckConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct EndpointConfig {
+    pub(crate) uri: Url,
+    /// Share of read requests relative to other endpoints (`uri` has weight 1)
+    #[serde(default = "EndpointConfig::default_weight")]
+    pub(crate) weight: u32,
+}
+
+/// Periodic `getHealth` and `getSlot` checks of the endpoints (only if there are several of them),
+/// unhealthy or lagging endpoints get no requests until they recover.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct HealthCheckConfig {
+    /// 0 disables the checks, a failed request is still retried on another endpoint,
+    /// but the failed one isn't excluded from the following requests (nothing would bring it back)
+    pub(crate) interval_secs: u64,
+    /// Max slots an endpoint can be behind the most advanced one
+    pub(crate) max_slot_lag: u64,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self { interval_secs: 5, max_slot_lag: 50 }
+    }
+}
+
+impl EndpointConfig {
+    fn default_weight() -> u32 {
+        1
+    }
+}
+
+impl RpcConfig {
+    fn default_max_send_attempts() -> u32 {
+        3
+    }
+
+    fn default_wait_for() -> CommitmentLevel {
+        CommitmentLevel::Confirmed
+    }
+
+    /// Levels reported while waiting for a transaction up to `wait_for`:
+    /// finalization time is reported along with the confirmation one.
+    pub(crate) fn confirmation_levels(&self) -> Vec<CommitmentLevel> {
+        match self.wait_for {
+            CommitmentLevel::Finalized => vec![CommitmentLevel::Confirmed, CommitmentLevel::Finalized],
+            level => vec![level],
+        }
+    }
+
+    /// `ws_uri` or `uri` with a WebSocket scheme and the next port (the way `solana` CLI does).
+    pub(crate) fn ws_url(&self) -> url::Url {
+        if let Some(Url(url)) = &self.ws_uri {
+            return url.clone()
+        }
+        let mut url = self.uri.0.clone();
+        let scheme = match url.scheme() {
+            "https" => "wss",
+            _ => "ws",
+        };
+        let _ = url.set_scheme(scheme);
+        if let Some(port) = url.port() {
+            let _ = url.set_port(Some(port.saturating_add(1)));
+        }
+        url
+    }
+
+    /// `uri` with weight 1 followed by the additional endpoints.
+    pub(crate) fn all_endpoints(&self) -> Vec<EndpointConfig> {
+        std::iter::once(EndpointConfig { uri: self.uri.clone(), weight: EndpointConfig::default_weight() })
+            .chain(self.endpoints.iter().cloned())
+            .collect()
+    }
+}
+
+/// Retries of failed RPC requests (connection errors, HTTP 429 and 5xx, unhealthy node)
+/// with jittered exponential backoff.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct RetryConfig {
+    pub(crate) max_retries: u32,
+    pub(crate) initial_backoff_ms: u64,
+    pub(crate) max_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self { max_retries: 5, initial_backoff_ms: 250, max_backoff_ms: 10_000 }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct TokenConfig {
-    pub(crate) owner: KeypairSerde,
-    pub(crate) mint: KeypairSerde,
+    pub(crate) owner: KeypairSource,
+    pub(crate) mint: KeypairSource,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct TestConfig {
     pub(crate) mint: PubkeySerde,
     pub(crate) transfers: TestTransferCasesConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct TestTransferCasesConfig {
     pub(crate) sols: Vec<TestTransferConfig>,
     pub(crate) tokens: Vec<TestTransferConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct TestTransferConfig {
-    pub(crate) from: usize,
-    pub(crate) to: usize,
+    /// sender wallet: index or name
+    pub(crate) from: AddressRef,
+    /// receiver: wallet index or name, address book label or a public key
+    pub(crate) to: AddressRef,
     pub(crate) amount: f64,
+    /// Memo template, supports `{run_id}`, `{case}` and `{timestamp}` placeholders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) memo: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct Url(pub(crate) url::Url);
 pub(crate) struct KeypairSerde(pub(crate) Keypair);
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub(crate) struct KeypairList(pub(crate) Vec<KeypairSerde>);
 #[derive(Clone)]
 pub(crate) struct PubkeySerde(pub(crate) Pubkey);
 
 impl Config {
-    pub(crate) async fn try_from_cli(cli: &Cli) -> ConfigResult<Self> {
-        let config_yaml_file = std::fs::File::open(&cli.config_file).context(ReadFailedSnafu{ path: cli.config_file.clone() })?;
-        let config_parse_result = serde_yaml::from_reader::<_, Config>(config_yaml_file);
-        let config = config_parse_result.context(ParseFailedSnafu { path: cli.config_file.clone() })?;
-        // ... there is a place for re-declaring some of the config values using cli arguments and environment variables
+    /// Reads the config file, selects the profile and applies the overrides (environment variables and cli arguments)
+    /// on top of it. Keypair files are looked up relative to the config file directory.
+    pub(crate) async fn load(path: &Path, profile: Option<&str>, overrides: &ConfigOverrides) -> ConfigResult<Self> {
+        let mut config = Self::read(path)?;
+        if let Some(profile) = profile {
+            config.select_profile(profile)?;
+        }
+        config.apply_overrides(overrides)?;
+        config.resolve_keypairs(path.parent().unwrap_or(Path::new("")))?;
         Ok(config)
     }
+
+    /// The config file as is: the top level sections, no overrides, keypairs aren't resolved.
+    pub(crate) fn read(path: &Path) -> ConfigResult<Self> {
+        let path_str = path.to_string_lossy().to_string();
+        let config_yaml_file = std::fs::File::open(path).context(ReadFailedSnafu{ path: path_str.clone() })?;
+        let config_parse_result = serde_yaml::from_reader::<_, Config>(config_yaml_file);
+        config_parse_result.context(ParseFailedSnafu { path: path_str })
+    }
+
+    /// Replaces the top level `rpc`, `token` and `test` sections with the profile ones.
+    /// Cluster monikers (`localnet`, `devnet`, `testnet`, `mainnet-beta` or their first letters) select
+    /// the profile of that name if it's declared, the top level values with the cluster RPC URL otherwise
+    /// (the top level WebSocket URL and additional endpoints belong to another cluster and are dropped).
+    fn select_profile(&mut self, name: &str) -> ConfigResult<()> {
+        let (name, profile) = match (self.profiles.get(name), cluster_moniker(name)) {
+            (Some(profile), _) => (name, profile.clone()),
+            (None, Some((moniker, url))) => match self.profiles.get(moniker) {
+                Some(profile) => (moniker, profile.clone()),
+                None => (moniker, ProfileConfig {
+                    rpc: Some(RpcConfig {
+                        uri: Url(url::Url::parse(url).expect("cluster URLs are valid")),
+                        ws_uri: None,
+                        endpoints: Vec::new(),
+                        ..self.rpc.clone()
+                    }),
+                    ..ProfileConfig::default()
+                }),
+            },
+            (None, None) => return UnknownProfileSnafu { name }.fail(),
+        };
+        let ProfileConfig { rpc, token, test } = profile;
+        self.rpc = rpc.unwrap_or_else(|| self.rpc.clone());
+        self.token = token.unwrap_or_else(|| self.token.clone());
+        self.test = test.unwrap_or_else(|| self.test.clone());
+        self.active_profile = Some(name.to_string());
+        Ok(())
+    }
+
+    fn apply_overrides(&mut self, overrides: &ConfigOverrides) -> ConfigResult<()> {
+        if let Some(url) = &overrides.rpc_url {
+            self.rpc.uri = Url(url.clone());
+        }
+        if !overrides.rpc_endpoints.is_empty() {
+            self.rpc.endpoints = overrides.rpc_endpoints.iter()
+                .map(|url| EndpointConfig { uri: Url(url.clone()), weight: EndpointConfig::default_weight() })
+                .collect();
+        }
+        if let Some(url) = &overrides.ws_url {
+            self.rpc.ws_uri = Some(Url(url.clone()));
+        }
+        if let Some(commitment) = overrides.commitment {
+            self.rpc.commitment = commitment;
+        }
+        if let Some(wait_for) = overrides.wait_for {
+            self.rpc.wait_for = wait_for;
+        }
+        if let Some(rate_limit) = overrides.rate_limit {
+            self.rpc.rate_limit_per_sec = Some(rate_limit);
+        }
+        if let Some(max_concurrent_requests) = overrides.max_concurrent_requests {
+            self.rpc.max_concurrent_requests = Some(max_concurrent_requests);
+        }
+        if let Some(max_retries) = overrides.max_retries {
+            self.rpc.retry.max_retries = max_retries;
+        }
+        if let Some(max_send_attempts) = overrides.max_send_attempts {
+            self.rpc.max_send_attempts = max_send_attempts;
+        }
+        if let Some(owner) = &overrides.token_owner {
+            self.token.owner = owner.clone().with_absolute_path();
+        }
+        if let Some(mint) = &overrides.token_mint {
+            self.token.mint = mint.clone().with_absolute_path();
+        }
+        if let Some(mint) = &overrides.test_mint {
+            self.test.mint = mint.clone();
+        }
+        if let Some(yaml) = &overrides.sol_transfers {
+            self.test.transfers.sols = serde_yaml::from_str(yaml)
+                .context(OverrideParseFailedSnafu { name: "test.transfers.sols" })?;
+        }
+        if let Some(yaml) = &overrides.token_transfers {
+            self.test.transfers.tokens = serde_yaml::from_str(yaml)
+                .context(OverrideParseFailedSnafu { name: "test.transfers.tokens" })?;
+        }
+        if let Some(phrase) = &overrides.seed_phrase {
+            let passphrase = self.seed.take().map(|seed| seed.passphrase).unwrap_or_default();
+            self.seed = Some(SeedConfig { phrase: phrase.clone(), passphrase });
+        }
+        if let (Some(passphrase), Some(seed)) = (&overrides.seed_passphrase, &mut self.seed) {
+            seed.passphrase = passphrase.clone();
+        }
+        if !overrides.wallets.is_empty() {
+            self.wallet_sources = overrides.wallets.iter().cloned()
+                .map(|source| WalletEntry::from(source.with_absolute_path()))
+                .collect();
+        }
+        if let Some(keystore_path) = &overrides.keystore_path {
+            // the config keystore path is relative to the config file directory, the override one to the current one
+            self.keystore = Some(std::path::absolute(keystore_path).unwrap_or_else(|_| keystore_path.clone()));
+        }
+        self.address_book.extend(overrides.address_book.iter().cloned());
+        Ok(())
+    }
+
+    /// Loads the token keypairs and the wallets, `base_dir` is the directory of relative keypair file paths.
+    fn resolve_keypairs(&mut self, base_dir: &Path) -> ConfigResult<()> {
+        let seed = self.seed.as_ref().map(SeedConfig::to_seed).transpose()?;
+        let mut fail = |_: String, e: ConfigError| Err(e);
+        self.resolve_token_keypairs(seed.as_deref(), base_dir, &mut fail)?;
+        self.resolve_wallets(seed.as_deref(), base_dir, &mut fail)
+    }
+
+    /// `on_error` gets the YAML path of a keypair that can't be resolved, the keypairs stay unresolved
+    /// unless it fails.
+    fn resolve_token_keypairs(
+        &mut self,
+        seed: Option<&[u8]>,
+        base_dir: &Path,
+        on_error: &mut dyn FnMut(String, ConfigError) -> ConfigResult<()>,
+    ) -> ConfigResult<()> {
+        let token_path = self.section_path("token");
+        let owner = self.token.owner.resolve(seed, base_dir);
+        let mint = self.token.mint.resolve(seed, base_dir);
+        match (owner, mint) {
+            (Ok(owner), Ok(mint)) => self.token_keypairs = Some(TokenKeypairs { owner, mint }),
+            (owner, mint) => {
+                if let Err(e) = owner {
+                    on_error(format!("{token_path}.owner"), e)?;
+                }
+                if let Err(e) = mint {
+                    on_error(format!("{token_path}.mint"), e)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `on_error` gets the YAML path of a wallet that can't be resolved, the wallet is skipped unless it fails.
+    fn resolve_wallets(
+        &mut self,
+        seed: Option<&[u8]>,
+        base_dir: &Path,
+        on_error: &mut dyn FnMut(String, ConfigError) -> ConfigResult<()>,
+    ) -> ConfigResult<()> {
+        let (stored, keystore_origin) = match &self.keystore {
+            Some(keystore_path) => {
+                let decrypted = Keystore::read(&base_dir.join(keystore_path))
+                    .and_then(|keystore| Ok((keystore, keystore::read_password(false)?)))
+                    .and_then(|(keystore, password)| keystore.decrypt(&password))
+                    .context(KeystoreSnafu);
+                match decrypted {
+                    Ok(stored) => (stored, format!("keystore:{}", keystore_path.to_string_lossy())),
+                    // references to the keystore wallets aren't checked then
+                    Err(e) => return on_error("keystore".to_string(), e),
+                }
+            }
+            None => (Vec::new(), String::new()),
+        };
+        let mut referenced = vec![false; stored.len()];
+        let mut entries = Vec::with_capacity(self.wallet_sources.len() + stored.len());
+        for (i, entry) in self.wallet_sources.iter().enumerate() {
+            let path = format!("wallets[{i}]");
+            let KeypairSource::Keystore(index) = entry.source else {
+                entries.push((entry.clone(), entry.source.to_string(), path));
+                continue
+            };
+            let Some(stored_entry) = stored.get(index) else {
+                on_error(path, UnknownKeystoreWalletSnafu { index, count: stored.len() }.build())?;
+                continue
+            };
+            referenced[index] = true;
+            // names and tags of the config entry take precedence
+            let entry = WalletEntry {
+                name: entry.name.clone().or_else(|| stored_entry.name.clone()),
+                tags: match entry.tags.is_empty() {
+                    true => stored_entry.tags.clone(),
+                    false => entry.tags.clone(),
+                },
+                source: stored_entry.source.clone(),
+            };
+            entries.push((entry, format!("{keystore_origin}#{index}"), path));
+        }
+        entries.extend(
+            stored.into_iter().zip(referenced)
+                .filter(|(_, referenced)| !referenced)
+                .map(|(entry, _)| (entry, keystore_origin.clone(), "keystore".to_string()))
+        );
+        let mut wallets = Vec::with_capacity(entries.len());
+        let mut labels = Vec::with_capacity(entries.len());
+        for label in self.address_book.keys() {
+            if !AddressRef::is_name(label) {
+                on_error(format!("address_book.{label}"), AmbiguousNameSnafu { name: label.clone() }.build())?;
+            }
+        }
+        let mut names = self.address_book.keys().cloned().collect::<std::collections::HashSet<_>>();
+        for (WalletEntry { name, tags, source }, origin, path) in entries {
+            if let Some(name) = &name {
+                if !AddressRef::is_name(name) {
+                    on_error(path.clone(), AmbiguousNameSnafu { name: name.clone() }.build())?;
+                }
+                if !names.insert(name.clone()) {
+                    on_error(path.clone(), DuplicateNameSnafu { name: name.clone() }.build())?;
+                }
+            }
+            match source.resolve(seed, base_dir) {
+                Ok(wallet) => {
+                    wallets.push(wallet);
+                    labels.push(WalletLabel { name, tags, origin });
+                }
+                Err(e) => on_error(path, e)?,
+            }
+        }
+        self.wallets = KeypairList(wallets);
+        self.wallet_labels = labels;
+        Ok(())
+    }
+
+    /// The config with the top level sections followed by the config with each declared profile selected.
+    pub(crate) fn with_each_profile(&self) -> Vec<Self> {
+        std::iter::once(self.clone())
+            .chain(self.profiles.keys().map(|name| {
+                let mut config = self.clone();
+                config.select_profile(name).expect("declared profiles can be selected");
+                config
+            }))
+            .collect()
+    }
+
+    /// Checks the config file as is (no overrides, every profile): keypairs that can't be resolved
+    /// and [`Config::validate`] issues. Only a file that can't be read or parsed is an error.
+    pub(crate) fn validate_file(path: &Path) -> ConfigResult<Vec<ConfigIssue>> {
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        let mut config = Self::read(path)?;
+        let mut issues = Vec::new();
+        let mut collect = |path: String, e: ConfigError| {
+            issues.push(ConfigIssue { path, msg: e.to_string() });
+            Ok(())
+        };
+        let seed = match config.seed.as_ref().map(SeedConfig::to_seed).transpose() {
+            Ok(seed) => seed,
+            Err(e) => { collect("seed.phrase".to_string(), e)?; None }
+        };
+        config.resolve_wallets(seed.as_deref(), base_dir, &mut collect)?;
+        let wallets_resolved = issues.is_empty();
+        for mut config in config.with_each_profile() {
+            let mut profile_issues = Vec::new();
+            config.resolve_token_keypairs(seed.as_deref(), base_dir, &mut |path, e| {
+                profile_issues.push(ConfigIssue { path, msg: e.to_string() });
+                Ok(())
+            })?;
+            if wallets_resolved && profile_issues.is_empty() {
+                profile_issues = config.validate();
+            }
+            issues.extend(profile_issues);
+        }
+        // profiles without their own sections repeat the top level issues
+        let mut seen = std::collections::HashSet::new();
+        issues.retain(|issue| seen.insert((issue.path.clone(), issue.msg.clone())));
+        Ok(issues)
+    }
+
+    pub(crate) fn token_keypairs(&self) -> &TokenKeypairs {
+        self.token_keypairs.as_ref().expect("token keypairs are resolved by Config::load")
+    }
+
+    /// Index of the owned wallet by its index, name or public key.
+    pub(crate) fn wallet_index(&self, reference: &AddressRef) -> ConfigResult<usize> {
+        let index = match reference {
+            AddressRef::Index(i) => Some(*i).filter(|i| *i < self.wallets.0.len()),
+            AddressRef::Name(name) => self.wallet_labels.iter()
+                .position(|label| label.name.as_ref() == Some(name)),
+            AddressRef::Pubkey(pk) => self.wallets.0.iter()
+                .position(|kp| kp.0.pubkey() == pk.0),
+        };
+        index.context(UnknownWalletSnafu { reference: reference.to_string() })
+    }
+
+    /// Public key of the wallet or the address book entry.
+    pub(crate) fn address(&self, reference: &AddressRef) -> ConfigResult<Pubkey> {
+        match reference {
+            AddressRef::Pubkey(pk) => Ok(pk.0),
+            AddressRef::Name(name) if self.address_book.contains_key(name) => Ok(self.address_book[name].0),
+            reference => Ok(self.wallets.0[self.wallet_index(reference)?].0.pubkey()),
+        }
+    }
+
+    /// Wallet name or address book label of the address.
+    pub(crate) fn label(&self, pubkey: &Pubkey) -> Option<&str> {
+        self.wallets.0.iter().zip(self.wallet_labels.iter())
+            .find(|(kp, _)| kp.0.pubkey() == *pubkey)
+            .and_then(|(_, label)| label.name.as_deref())
+            .or_else(|| self.address_book.iter()
+                .find(|(_, pk)| pk.0 == *pubkey)
+                .map(|(label, _)| label.as_str()))
+    }
+
+    /// The address with its label if it has one, e.g. `alice (Fk3...)`.
+    pub(crate) fn display_address(&self, pubkey: &Pubkey) -> String {
+        match self.label(pubkey) {
+            Some(label) => format!("{label} ({pubkey})"),
+            None => pubkey.to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// The config as a YAML value with the active profile, secret keys are replaced with their public keys
+    /// and the seed with a placeholder unless `reveal_secrets` is set.
+    pub(crate) fn to_value(&self, reveal_secrets: bool) -> ConfigResult<serde_yaml::Value> {
+        let mut value = serde_yaml::to_value(self).context(ValueSerializationFailedSnafu)?;
+        if !reveal_secrets {
+            redact_token(&mut value["token"], &self.token);
+            for (name, profile) in self.profiles.iter() {
+                if let Some(token) = &profile.token {
+                    redact_token(&mut value["profiles"][name.as_str()]["token"], token);
+                }
+            }
+            for (i, entry) in self.wallet_sources.iter().enumerate() {
+                if let KeypairSource::Inline(kp) = &entry.source {
+                    let item = &mut value["wallets"][i];
+                    match item.is_mapping() {
+                        true => item["keypair"] = kp.pubkey().to_string().into(),
+                        false => *item = kp.pubkey().to_string().into(),
+                    }
+                }
+            }
+            if let Some(seed) = value.get_mut("seed").and_then(|seed| seed.as_mapping_mut()) {
+                for (_, secret) in seed.iter_mut() {
+                    *secret = REDACTED.into();
+                }
+            }
+        }
+        let profile = self.active_profile.as_deref().unwrap_or("default");
+        let mut mapping = serde_yaml::Mapping::from_iter([("profile".into(), profile.into())]);
+        mapping.extend(value.as_mapping().cloned().unwrap_or_default());
+        Ok(serde_yaml::Value::Mapping(mapping))
+    }
+}
+
+const REDACTED: &str = "<redacted>";
+
+fn redact_token(value: &mut serde_yaml::Value, token: &TokenConfig) {
+    for (key, source) in [("owner", &token.owner), ("mint", &token.mint)] {
+        if let KeypairSource::Inline(kp) = source {
+            value[key] = kp.pubkey().to_string().into();
+        }
+    }
+}
+
+/// JSON Schema of the config file format.
+pub(crate) fn config_schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(Config)
+}
+
+fn commitment_schema(_: &mut SchemaGenerator) -> Schema {
+    serde_json::from_value(serde_json::json!({
+        "type": "string",
+        "enum": ["processed", "confirmed", "finalized"],
+    })).expect("valid schema")
+}
+
+/// Config problem found by [`Config::validate`], `path` is the YAML path like `test.transfers.sols[3].from`.
+#[derive(Debug, Clone)]
+pub(crate) struct ConfigIssue {
+    pub(crate) path: String,
+    pub(crate) msg: String,
+}
+
+impl Config {
+    /// Semantic checks the parsing doesn't do: test transfer parties and amounts, the tested mint, duplicate wallets.
+    pub(crate) fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let mut issue = |path: String, msg: String| issues.push(ConfigIssue { path, msg });
+        let test_path = self.section_path("test");
+        for (kind, cases) in [("sols", &self.test.transfers.sols), ("tokens", &self.test.transfers.tokens)] {
+            for (i, case) in cases.iter().enumerate() {
+                let case_path = format!("{test_path}.transfers.{kind}[{i}]");
+                if let Err(e) = self.wallet_index(&case.from) {
+                    issue(format!("{case_path}.from"), self.unknown_wallet_msg(&case.from, e));
+                }
+                if let Err(e) = self.address(&case.to) {
+                    issue(format!("{case_path}.to"), self.unknown_wallet_msg(&case.to, e));
+                }
+                if !(case.amount.is_finite() && case.amount > 0.0) {
+                    issue(format!("{case_path}.amount"), format!("amount must be positive, got {}", case.amount));
+                }
+            }
+        }
+        if self.rpc.max_send_attempts == 0 {
+            issue(format!("{}.max_send_attempts", self.section_path("rpc")), "at least one attempt is required".to_string());
+        }
+        let token_mint = self.token_keypairs().mint.pubkey();
+        if self.test.mint.0 != token_mint.0 {
+            issue(
+                format!("{test_path}.mint"),
+                format!("{} doesn't match the {}.mint pubkey {token_mint}", self.test.mint, self.section_path("token")),
+            );
+        }
+        let mut seen = std::collections::HashMap::new();
+        for (i, kp) in self.wallets.0.iter().enumerate() {
+            if let Some(first) = seen.insert(kp.0.pubkey(), i) {
+                let path = match i < self.wallet_sources.len() {
+                    true => format!("wallets[{i}]"),
+                    false => "keystore".to_string(),
+                };
+                issue(path, format!("wallet {} duplicates wallet #{first}", kp.pubkey()));
+            }
+        }
+        issues
+    }
+
+    /// YAML path of the `rpc`, `token` or `test` section taking the active profile into account.
+    pub(crate) fn section_path(&self, section: &str) -> String {
+        let in_profile = self.active_profile.as_ref()
+            .and_then(|name| self.profiles.get(name).map(|profile| (name, profile)))
+            .filter(|(_, profile)| match section {
+                "rpc" => profile.rpc.is_some(),
+                "token" => profile.token.is_some(),
+                "test" => profile.test.is_some(),
+                _ => false,
+            });
+        match in_profile {
+            Some((name, _)) => format!("profiles.{name}.{section}"),
+            None => section.to_string(),
+        }
+    }
+
+    fn unknown_wallet_msg(&self, reference: &AddressRef, error: ConfigError) -> String {
+        match reference {
+            AddressRef::Index(i) => format!("wallet index {i} is out of range ({} wallets)", self.wallets.0.len()),
+            _ => error.to_string(),
+        }
+    }
+}
+
+impl From<KeypairSource> for WalletEntry {
+    fn from(source: KeypairSource) -> Self {
+        Self { name: None, tags: Vec::new(), source }
+    }
+}
+
+impl WalletEntry {
+    /// Single line YAML representation (for config file editing).
+    pub(crate) fn to_yaml_item(&self) -> String {
+        if self.name.is_none() && self.tags.is_empty() {
+            return self.source.to_yaml_item()
+        }
+        let mut fields = Vec::new();
+        if let Some(name) = &self.name {
+            fields.push(format!("name: {}", yaml_scalar(name)));
+        }
+        if !self.tags.is_empty() {
+            fields.push(format!("tags: [{}]", self.tags.iter().map(|t| yaml_scalar(t)).collect::<Vec<_>>().join(", ")));
+        }
+        fields.push(format!("keypair: {}", self.source.to_yaml_item()));
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
+
+impl Serialize for WalletEntry {
+    fn serialize<S: Serializer,>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.name.is_none() && self.tags.is_empty() {
+            return self.source.serialize(serializer)
+        }
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(name) = &self.name {
+            map.serialize_entry("name", name)?;
+        }
+        if !self.tags.is_empty() {
+            map.serialize_entry("tags", &self.tags)?;
+        }
+        map.serialize_entry("keypair", &self.source)?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for WalletEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Named {
+            name: Option<String>,
+            #[serde(default)]
+            tags: Vec<String>,
+            keypair: KeypairSource,
+        }
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        let is_named = value.as_mapping().is_some_and(|map| map.contains_key("keypair"));
+        match is_named {
+            true => serde_yaml::from_value::<Named>(value)
+                .map(|Named { name, tags, keypair }| WalletEntry { name, tags, source: keypair })
+                .map_err(serde::de::Error::custom),
+            false => serde_yaml::from_value::<KeypairSource>(value)
+                .map(WalletEntry::from)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Display for WalletLabel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts = self.name.iter().cloned().collect::<Vec<_>>();
+        parts.extend(self.tags.iter().map(|tag| format!("#{tag}")));
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl FromStr for AddressRef {
+    type Err = std::convert::Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(index) = s.parse::<usize>() {
+            return Ok(AddressRef::Index(index))
+        }
+        Ok(match Pubkey::from_str(s) {
+            Ok(pk) => AddressRef::Pubkey(PubkeySerde(pk)),
+            Err(_) => AddressRef::Name(s.to_string()),
+        })
+    }
+}
+
+impl AddressRef {
+    /// Whether the string is referred to as a name, not as a wallet index or a public key.
+    pub(crate) fn is_name(s: &str) -> bool {
+        matches!(AddressRef::from_str(s), Ok(AddressRef::Name(_)))
+    }
+}
+
+impl Display for AddressRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressRef::Index(i) => write!(f, "{i}"),
+            AddressRef::Pubkey(pk) => write!(f, "{pk}"),
+            AddressRef::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl Serialize for AddressRef {
+    fn serialize<S: Serializer,>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            AddressRef::Index(i) => serializer.serialize_u64(*i as u64),
+            reference => serializer.serialize_str(&reference.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AddressRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Index(usize),
+            String(String),
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Index(i) => AddressRef::Index(i),
+            Repr::String(s) => AddressRef::from_str(&s).unwrap_or_else(|e| match e {}),
+        })
+    }
+}
+
+/// Plain YAML scalar if possible, otherwise a quoted one.
+fn yaml_scalar(value: &str) -> String {
+    let is_plain = !value.is_empty() && value.chars().all(|c| c.is_alphanumeric() || "-_.".contains(c));
+    match is_plain {
+        true => value.to_string(),
+        false => format!("{value:?}"),
+    }
+}
+
+impl SeedConfig {
+    /// Generates a new random BIP39 mnemonic (12 or 24 words).
+    pub(crate) fn generate(word_count: usize, passphrase: String) -> ConfigResult<Self> {
+        let mnemonic_type = MnemonicType::for_word_count(word_count)
+            .map_err(|e| ConfigError::InvalidSeedPhrase { msg: e.to_string() })?;
+        let mnemonic = Mnemonic::new(mnemonic_type, Language::English);
+        Ok(Self { phrase: mnemonic.phrase().to_string(), passphrase })
+    }
+
+    /// Validates the phrase and computes the BIP39 seed.
+    pub(crate) fn to_seed(&self) -> ConfigResult<Vec<u8>> {
+        Mnemonic::from_phrase(&self.phrase, Language::English)
+            .map_err(|e| ConfigError::InvalidSeedPhrase { msg: e.to_string() })?;
+        Ok(generate_seed_from_seed_phrase_and_passphrase(&self.phrase, &self.passphrase))
+    }
+
+    pub(crate) fn derive_wallets(&self, indices: impl IntoIterator<Item = u32>) -> ConfigResult<KeypairList> {
+        let seed = self.to_seed()?;
+        Ok(KeypairList(
+            indices.into_iter()
+                .map(|i| derive_keypair(&seed, i))
+                .collect::<ConfigResult<_>>()?
+        ))
+    }
+}
+
+impl core::fmt::Debug for SeedConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SeedConfig")
+            .field("phrase", &REDACTED)
+            .field("passphrase", &REDACTED)
+            .finish()
+    }
+}
+
+impl KeypairSource {
+    /// Loads the keypair, a relative file path is taken from `base_dir`
+    /// (derived keypairs are resolved by the config, because they need the seed).
+    pub(crate) fn load(&self, base_dir: &Path) -> ConfigResult<KeypairSerde> {
+        match self {
+            KeypairSource::Inline(kp) => Ok(kp.clone()),
+            KeypairSource::File(path) => {
+                let path = base_dir.join(path);
+                let path_str = path.to_string_lossy().to_string();
+                let file = std::fs::File::open(path).context(ReadFailedSnafu { path: path_str.clone() })?;
+                let bytes: Vec<u8> = serde_json::from_reader(file)
+                    .context(KeypairFileParseFailedSnafu { path: path_str.clone() })?;
+                KeypairSerde::from_bytes(&bytes)
+                    .map_err(|msg| ConfigError::InvalidKeypair { source_name: format!("file:{path_str}"), msg })
+            }
+            KeypairSource::Env(var) => {
+                let value = std::env::var(var).context(KeypairEnvVarSnafu { var: var.clone() })?;
+                let value = value.trim();
+                let parsed = match value.starts_with('[') {
+                    true => serde_json::from_str::<Vec<u8>>(value)
+                        .map_err(|e| e.to_string())
+                        .and_then(|bytes| KeypairSerde::from_bytes(&bytes)),
+                    false => KeypairSerde::from_base58(value),
+                };
+                parsed.map_err(|msg| ConfigError::InvalidKeypair { source_name: format!("env:{var}"), msg })
+            }
+            KeypairSource::Derived { .. } => MissingSeedPhraseSnafu.fail(),
+            KeypairSource::Keystore(_) => KeystoreReferenceSnafu.fail(),
+        }
+    }
+
+    /// Loads the keypair deriving it from the config `seed` if it's a derived one.
+    pub(crate) fn resolve(&self, seed: Option<&[u8]>, base_dir: &Path) -> ConfigResult<KeypairSerde> {
+        match self {
+            KeypairSource::Derived { derivation_index } => derive_keypair(
+                seed.context(MissingSeedPhraseSnafu)?,
+                *derivation_index,
+            ),
+            source => source.load(base_dir),
+        }
+    }
+
+    /// Makes a relative file path absolute against the current directory
+    /// (for sources given in the command line rather than in the config file).
+    pub(crate) fn with_absolute_path(self) -> Self {
+        match self {
+            KeypairSource::File(path) => KeypairSource::File(std::path::absolute(&path).unwrap_or(path)),
+            source => source,
+        }
+    }
+}
+
+impl KeypairSource {
+    /// Single line YAML representation (for config file editing).
+    pub(crate) fn to_yaml_item(&self) -> String {
+        match self {
+            KeypairSource::Derived { derivation_index } => format!("{{ derivation_index: {derivation_index} }}"),
+            KeypairSource::Inline(kp) => kp.to_string(),
+            source => source.to_string(),
+        }
+    }
+}
+
+impl Display for KeypairSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeypairSource::Inline(_) => write!(f, "inline"),
+            KeypairSource::File(path) => write!(f, "file:{}", path.to_string_lossy()),
+            KeypairSource::Env(var) => write!(f, "env:{var}"),
+            KeypairSource::Derived { derivation_index } => write!(f, "derived:m/44'/501'/{derivation_index}'/0'"),
+            KeypairSource::Keystore(index) => write!(f, "keystore:{index}"),
+        }
+    }
+}
+
+impl FromStr for KeypairSource {
+    type Err = ConfigError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(var) = s.strip_prefix("env:") {
+            return Ok(KeypairSource::Env(var.to_string()))
+        }
+        if let Some(path) = s.strip_prefix("file:") {
+            return Ok(KeypairSource::File(PathBuf::from(path)))
+        }
+        if let Some(index) = s.strip_prefix("keystore:") {
+            return index.parse().map(KeypairSource::Keystore)
+                .map_err(|e| ConfigError::InvalidKeypair { source_name: s.to_string(), msg: e.to_string() })
+        }
+        if s.contains('/') || s.contains('\\') || s.ends_with(".json") {
+            return Ok(KeypairSource::File(PathBuf::from(s)))
+        }
+        KeypairSerde::from_base58(s)
+            .map(KeypairSource::Inline)
+            .map_err(|msg| ConfigError::InvalidKeypair { source_name: "inline".into(), msg })
+    }
+}
+
+impl Serialize for KeypairSource {
+    fn serialize<S: Serializer,>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            KeypairSource::Inline(kp) => kp.serialize(serializer),
+            KeypairSource::Derived { derivation_index } => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("derivation_index", derivation_index)?;
+                map.end()
+            }
+            source => serializer.serialize_str(&source.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeypairSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            String(String),
+            Derived { derivation_index: u32 },
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::String(s) => KeypairSource::from_str(&s).map_err(serde::de::Error::custom),
+            Repr::Derived { derivation_index } if derivation_index >= DERIVATION_INDEX_LIMIT => Err(
+                serde::de::Error::custom(DerivationIndexOutOfRangeSnafu { index: derivation_index }.build())
+            ),
+            Repr::Derived { derivation_index } => Ok(KeypairSource::Derived { derivation_index }),
+        }
+    }
+}
+
+impl JsonSchema for KeypairSource {
+    fn schema_name() -> String {
+        "KeypairSource".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        serde_json::from_value(serde_json::json!({
+            "description": "Base58 encoded keypair, `file:<path>`, a path to a solana-cli keypair json file, `env:<VAR>`, \
+                `keystore:<N>` (N-th keystore wallet, in `wallets` only) or a keypair derived from the seed phrase",
+            "oneOf": [
+                { "type": "string" },
+                {
+                    "type": "object",
+                    "properties": {
+                        "derivation_index": { "type": "integer", "minimum": 0, "maximum": DERIVATION_INDEX_LIMIT - 1 },
+                    },
+                    "required": ["derivation_index"],
+                    "additionalProperties": false,
+                },
+            ],
+        })).expect("valid schema")
+    }
+}
+
+impl JsonSchema for WalletEntry {
+    fn schema_name() -> String {
+        "WalletEntry".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let source = serde_json::to_value(gen.subschema_for::<KeypairSource>()).expect("valid schema");
+        serde_json::from_value(serde_json::json!({
+            "oneOf": [
+                source,
+                {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "tags": { "type": "array", "items": { "type": "string" } },
+                        "keypair": source,
+                    },
+                    "required": ["keypair"],
+                    "additionalProperties": false,
+                },
+            ],
+        })).expect("valid schema")
+    }
+}
+
+impl JsonSchema for AddressRef {
+    fn schema_name() -> String {
+        "AddressRef".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        serde_json::from_value(serde_json::json!({
+            "description": "Wallet index, wallet name, address book label or a public key",
+            "oneOf": [{ "type": "integer", "minimum": 0 }, { "type": "string" }],
+        })).expect("valid schema")
+    }
+}
+
+impl JsonSchema for Url {
+    fn schema_name() -> String {
+        "Url".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        serde_json::from_value(serde_json::json!({ "type": "string", "format": "uri" })).expect("valid schema")
+    }
+}
+
+impl JsonSchema for PubkeySerde {
+    fn schema_name() -> String {
+        "Pubkey".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        serde_json::from_value(serde_json::json!({
+            "description": "Base58 encoded public key",
+            "type": "string",
+            "pattern": "^[1-9A-HJ-NP-Za-km-z]{32,44}$",
+        })).expect("valid schema")
+    }
+}
+
+impl JsonSchema for KeypairSerde {
+    fn schema_name() -> String {
+        "Keypair".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        serde_json::from_value(serde_json::json!({
+            "description": "Base58 encoded keypair",
+            "type": "string",
+        })).expect("valid schema")
+    }
+}
+
+/// Derivation indices are hardened, so they must be below 2^31 (greater ones alias the lower indices).
+pub(crate) const DERIVATION_INDEX_LIMIT: u32 = 0x8000_0000;
+
+/// Derives a keypair by the standard Solana derivation path `m/44'/501'/{index}'/0'`.
+pub(crate) fn derive_keypair(seed: &[u8], index: u32) -> ConfigResult<KeypairSerde> {
+    ensure!(index < DERIVATION_INDEX_LIMIT, DerivationIndexOutOfRangeSnafu { index });
+    let path = DerivationPath::new_bip44(Some(index), Some(0));
+    keypair_from_seed_and_derivation_path(seed, Some(path))
+        .map(KeypairSerde)
+        .map_err(|e| ConfigError::KeypairDerivationFailed { index, msg: e.to_string() })
+}
+
+impl TestTransferConfig {
+    pub(crate) const DEFAULT_MEMO: &'static str = "Test transfer {run_id}#{case}";
+
+    /// Renders the memo template of the case. A template without `{run_id}` or `{case}` gets ` [{run_id}#{case}]` suffix,
+    /// otherwise identical cases of a run (or the same case of different runs) would make identical transactions.
+    pub(crate) fn render_memo(&self, run_id: &str, case: usize) -> String {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let template = self.memo.as_deref().unwrap_or(Self::DEFAULT_MEMO);
+        let mut memo = template
+            .replace("{run_id}", run_id)
+            .replace("{case}", &case.to_string())
+            .replace("{timestamp}", &timestamp.to_string());
+        if !(template.contains("{run_id}") && template.contains("{case}")) {
+            memo.push_str(&format!(" [{run_id}#{case}]"));
+        }
+        memo
+    }
 }
 
 pub(crate) fn generate_wallets(count: usize) -> ConfigResult<KeypairList> {
@@ -76,6 +1165,7 @@ impl core::fmt::Debug for Url {
 }
 
 impl PubkeySerde {
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub(crate) fn to_string(&self) -> String {
         bs58::encode(self.0.to_bytes()).into_string()
     }
@@ -121,9 +1211,22 @@ impl KeypairSerde {
     pub(crate) fn pubkey(&self) -> PubkeySerde {
         PubkeySerde(self.0.pubkey())
     }
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub(crate) fn to_string(&self) -> String {
         bs58::encode(self.0.to_bytes()).into_string()
     }
+
+    pub(crate) fn from_base58(encoded: &str) -> Result<Self, String> {
+        let decoded = bs58::decode(encoded).into_vec()
+            .map_err(|e| format!("Can't parse wallet's base58 encoded string: cause: {e}"))?;
+        Self::from_bytes(&decoded)
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        Keypair::from_bytes(bytes)
+            .map(KeypairSerde)
+            .map_err(|e| format!("Can't parse keypair bytes: cause: {e}"))
+    }
 }
 
 impl Clone for KeypairSerde {
@@ -138,9 +1241,10 @@ impl Display for KeypairSerde {
     }
 }
 
+/// Only the public key, so secrets don't leak into debug output.
 impl core::fmt::Debug for KeypairSerde {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[{}] {}", self.pubkey().to_string(), self.to_string())
+        write!(f, "[{}] {REDACTED}", self.pubkey())
     }
 }
 
@@ -153,16 +1257,7 @@ impl Serialize for KeypairSerde {
 impl<'de> Deserialize<'de> for KeypairSerde {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let encoded = String::deserialize(deserializer)?;
-        let decoded = bs58::decode(encoded).into_vec().map_err(
-            |e| serde::de::Error::custom(
-                format!("Can't parse wallet's base58 encoded string: cause: {e}")
-            )
-        )?;
-        Ok(KeypairSerde(
-            Keypair::from_bytes(&decoded).map_err(|e| serde::de::Error::custom(
-                format!("Can't parse keypair bytes: cause: {e}")
-            ))?
-        ))
+        KeypairSerde::from_base58(&encoded).map_err(serde::de::Error::custom)
     }
 }
 
@@ -185,7 +1280,76 @@ impl core::fmt::Debug for KeypairList {
     }
 }
 
-type ConfigResult<T> = Result<T, ConfigError>;
+/// Canonical name and RPC URL of the well-known cluster moniker.
+pub(crate) fn cluster_moniker(name: &str) -> Option<(&'static str, &'static str)> {
+    match name {
+        "localnet" | "l" => Some(("localnet", "http://localhost:8899")),
+        "devnet" | "d" => Some(("devnet", "https://api.devnet.solana.com")),
+        "testnet" | "t" => Some(("testnet", "https://api.testnet.solana.com")),
+        "mainnet-beta" | "m" => Some(("mainnet-beta", "https://api.mainnet-beta.solana.com")),
+        _ => None,
+    }
+}
+
+/// RPC URL of the cluster moniker or the URL itself.
+pub(crate) fn cluster_url(cluster: &str) -> ConfigResult<url::Url> {
+    let url = cluster_moniker(cluster).map_or(cluster, |(_, url)| url);
+    url::Url::parse(url).context(InvalidClusterUrlSnafu { cluster })
+}
+
+/// Starter config: fresh token keypairs and wallets, the first half of wallets send SOLs and tokens to the second one.
+pub(crate) fn starter_config_yaml(wallet_count: usize, rpc_url: &url::Url) -> ConfigResult<String> {
+    let wallets = generate_wallets(wallet_count)?;
+    let (owner, mint) = (KeypairSerde(Keypair::new()), KeypairSerde(Keypair::new()));
+    let half = wallet_count / 2;
+    let cases = |amount: u32, first_memo: &str| match half {
+        0 => " []".to_string(),
+        _ => (0..half)
+            .map(|i| match i {
+                0 => format!("\n      - {{ from: {i}, to: {}, amount: {amount}, memo: \"{first_memo}\" }}", i + half),
+                _ => format!("\n      - {{ from: {i}, to: {}, amount: {amount} }}", i + half),
+            })
+            .collect(),
+    };
+    let wallets_yaml = match wallets.0.is_empty() {
+        true => " []".to_string(),
+        false => wallets.0.iter().map(|kp| format!("\n  - {kp}")).collect(),
+    };
+    Ok(format!("\
+rpc:
+  uri: {rpc_url}
+  # processed, confirmed or finalized; commitment defaults to finalized, wait_for to confirmed
+  commitment: confirmed
+  wait_for: confirmed
+token:
+  owner: {owner}
+  mint: {mint}
+test:
+  mint: {mint_pubkey}
+  transfers:
+    # memo placeholders: {{run_id}}, {{case}}, {{timestamp}}; default: \"Test transfer {{run_id}}#{{case}}\"
+    sols:{sols}
+    tokens:{tokens}
+# test transfers refer to wallets by index or name, e.g. {{ name: alice, tags: [player1], keypair: <keypair> }}
+wallets:{wallets_yaml}
+",
+        mint_pubkey = mint.pubkey(),
+        sols = cases(1, "SOL transfer {run_id}#{case}"),
+        tokens = cases(10, "Token transfer {run_id}#{case} at {timestamp}"),
+    ))
+}
+
+/// Resolves the `--config` value: a path or `env:VAR` with the path in the environment variable.
+pub(crate) fn resolve_config_path(value: &str) -> ConfigResult<PathBuf> {
+    match value.strip_prefix("env:") {
+        Some(var) => std::env::var(var)
+            .map(PathBuf::from)
+            .context(ConfigPathEnvVarSnafu { var }),
+        None => Ok(PathBuf::from(value)),
+    }
+}
+
+pub(crate) type ConfigResult<T> = Result<T, ConfigError>;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub))]
@@ -196,4 +1360,381 @@ pub(crate) enum ConfigError {
     ParseFailed { path: String, source: serde_yaml::Error },
     #[snafu(display("Yaml serialization failed: path: {path}; cause: {source}"))]
     YamlSerializationFailed { path: String, source: serde_yaml::Error },
+    #[snafu(display("Invalid keypair: source: {source_name}; cause: {msg}"))]
+    InvalidKeypair { source_name: String, msg: String },
+    #[snafu(display("Parse keypair file failed: path: {path}; cause: {source}"))]
+    KeypairFileParseFailed { path: String, source: serde_json::Error },
+    #[snafu(display("Keypair environment variable {var} is not available: {source}"))]
+    KeypairEnvVarError { var: String, source: std::env::VarError },
+    #[snafu(display("Keystore error: {source}"))]
+    KeystoreError { source: keystore::KeystoreError },
+    #[snafu(display("Duplicate wallet name or address label: {name}"))]
+    DuplicateName { name: String },
+    #[snafu(display("Wallet name or address label {name} can't be referred to, it's taken for a wallet index or a public key"))]
+    AmbiguousName { name: String },
+    #[snafu(display("Unknown wallet: {reference}"))]
+    UnknownWallet { reference: String },
+    #[snafu(display("Invalid seed phrase: {msg}"))]
+    InvalidSeedPhrase { msg: String },
+    #[snafu(display("Derived wallets require the seed phrase (config \"seed.phrase\")"))]
+    MissingSeedPhrase,
+    #[snafu(display("Keystore wallet #{index} is referenced, but the keystore has {count} wallets"))]
+    UnknownKeystoreWallet { index: usize, count: usize },
+    #[snafu(display("Keystore wallets can be referenced only in \"wallets\""))]
+    KeystoreReference,
+    #[snafu(display("Keypair derivation failed: index: {index}; cause: {msg}"))]
+    KeypairDerivationFailed { index: u32, msg: String },
+    #[snafu(display("Derivation index {index} is out of range, hardened indices are below {DERIVATION_INDEX_LIMIT}"))]
+    DerivationIndexOutOfRange { index: u32 },
+    #[snafu(display("Config file path environment variable {var} is not available: {source}"))]
+    ConfigPathEnvVarError { var: String, source: std::env::VarError },
+    #[snafu(display("Config serialization failed: {source}"))]
+    ValueSerializationFailed { source: serde_yaml::Error },
+    #[snafu(display("Invalid cluster: {cluster}; cause: {source}"))]
+    InvalidClusterUrl { cluster: String, source: url::ParseError },
+    #[snafu(display("Unknown profile: {name}"))]
+    UnknownProfile { name: String },
+    #[snafu(display("Can't parse config override {name}: {source}"))]
+    OverrideParseFailed { name: String, source: serde_yaml::Error },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_keypair() -> KeypairSerde {
+        KeypairSerde(Keypair::new())
+    }
+
+    /// The config of the YAML with the keypairs resolved (no seed, relative paths are taken from the current directory).
+    fn config(yaml: &str) -> ConfigResult<Config> {
+        let mut config = serde_yaml::from_str::<Config>(yaml).expect("valid config");
+        config.resolve_keypairs(Path::new(""))?;
+        Ok(config)
+    }
+
+    fn config_yaml(wallets: &str, address_book: &str) -> String {
+        format!("\
+rpc:
+  uri: http://localhost:8899
+token:
+  owner: {owner}
+  mint: {mint}
+test:
+  mint: 11111111111111111111111111111111
+  transfers:
+    sols: []
+    tokens: []
+wallets:{wallets}
+address_book:{address_book}
+",
+            owner = new_keypair(),
+            mint = new_keypair(),
+        )
+    }
+
+    fn config_with_profiles(profiles: &str) -> Config {
+        let yaml = format!("{}profiles:{profiles}", config_yaml(" []", " {}"));
+        serde_yaml::from_str::<Config>(&yaml).expect("valid config")
+    }
+
+    const DEVNET_PROFILE: &str = "
+  devnet:
+    rpc:
+      uri: http://devnet.example.com
+  stage:
+    rpc:
+      uri: http://stage.example.com
+    test:
+      mint: So11111111111111111111111111111111111111112
+      transfers: { sols: [], tokens: [] }
+";
+
+    #[test]
+    fn declared_profile_replaces_its_sections() {
+        let mut config = config_with_profiles(DEVNET_PROFILE);
+        let token_owner = config.token.owner.to_yaml_item();
+        config.select_profile("stage").unwrap();
+        assert_eq!(config.active_profile.as_deref(), Some("stage"));
+        assert_eq!(config.rpc.uri.0.as_str(), "http://stage.example.com/");
+        assert_eq!(config.test.mint.to_string(), "So11111111111111111111111111111111111111112");
+        // the missing section is taken from the top level
+        assert_eq!(config.token.owner.to_yaml_item(), token_owner);
+        assert_eq!(config.section_path("rpc"), "profiles.stage.rpc");
+        assert_eq!(config.section_path("test"), "profiles.stage.test");
+        assert_eq!(config.section_path("token"), "token");
+    }
+
+    #[test]
+    fn moniker_selects_declared_profile() {
+        let mut config = config_with_profiles(DEVNET_PROFILE);
+        config.select_profile("d").unwrap();
+        assert_eq!(config.active_profile.as_deref(), Some("devnet"));
+        assert_eq!(config.rpc.uri.0.as_str(), "http://devnet.example.com/");
+        assert_eq!(config.section_path("rpc"), "profiles.devnet.rpc");
+        assert_eq!(config.section_path("test"), "test");
+    }
+
+    #[test]
+    fn moniker_without_profile_keeps_top_level_sections() {
+        let mut config = config_with_profiles(DEVNET_PROFILE);
+        config.rpc.commitment = CommitmentLevel::Processed;
+        config.rpc.ws_uri = Some(Url(url::Url::parse("ws://localhost:8900").unwrap()));
+        config.rpc.endpoints = vec![EndpointConfig {
+            uri: Url(url::Url::parse("http://localhost:8898").unwrap()),
+            weight: EndpointConfig::default_weight(),
+        }];
+        let test_mint = config.test.mint.to_string();
+        config.select_profile("testnet").unwrap();
+        assert_eq!(config.active_profile.as_deref(), Some("testnet"));
+        assert_eq!(config.rpc.uri.0.as_str(), "https://api.testnet.solana.com/");
+        // the localnet WebSocket URL and endpoints are dropped, the rest is kept
+        assert!(config.rpc.ws_uri.is_none());
+        assert!(config.rpc.endpoints.is_empty());
+        assert_eq!(config.rpc.commitment, CommitmentLevel::Processed);
+        assert_eq!(config.test.mint.to_string(), test_mint);
+        for section in ["rpc", "token", "test"] {
+            assert_eq!(config.section_path(section), section);
+        }
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let mut config = config_with_profiles(DEVNET_PROFILE);
+        assert!(matches!(
+            config.select_profile("staging"),
+            Err(ConfigError::UnknownProfile { name }) if name == "staging",
+        ));
+        assert_eq!(config.active_profile, None);
+        assert_eq!(config.section_path("rpc"), "rpc");
+    }
+
+    /// Every string scalar of the value.
+    fn strings(value: &serde_yaml::Value) -> Vec<String> {
+        match value {
+            serde_yaml::Value::String(s) => vec![s.clone()],
+            serde_yaml::Value::Sequence(items) => items.iter().flat_map(strings).collect(),
+            serde_yaml::Value::Mapping(map) => map.iter().flat_map(|(k, v)| strings(k).into_iter().chain(strings(v))).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn to_value_redacts_secrets() {
+        let (bare, named, profile_owner) = (new_keypair(), new_keypair(), new_keypair());
+        let wallets = format!(
+            "\n  - {bare}\n  - {{ name: alice, tags: [p1], keypair: {named} }}\n  - file:alice.json\n  - {{ derivation_index: 3 }}"
+        );
+        let yaml = format!(
+            "{}seed:\n  phrase: secret words\n  passphrase: secret pass\nprofiles:\n  stage:\n    token:\n      owner: {profile_owner}\n      mint: env:MINT\n",
+            config_yaml(&wallets, " {}"),
+        );
+        let config = serde_yaml::from_str::<Config>(&yaml).expect("valid config");
+        let KeypairSource::Inline(owner) = &config.token.owner else { panic!("inline owner") };
+        let KeypairSource::Inline(mint) = &config.token.mint else { panic!("inline mint") };
+        let secrets = [&bare, &named, &profile_owner, owner, mint].map(|kp| kp.to_string());
+
+        let value = config.to_value(false).unwrap();
+        assert_eq!(value["profile"], "default");
+        assert_eq!(value["wallets"][0], bare.pubkey().to_string().as_str());
+        assert_eq!(value["wallets"][1]["keypair"], named.pubkey().to_string().as_str());
+        assert_eq!(value["wallets"][1]["name"], "alice");
+        assert_eq!(value["wallets"][2], "file:alice.json");
+        assert_eq!(value["wallets"][3]["derivation_index"], 3);
+        assert_eq!(value["token"]["owner"], owner.pubkey().to_string().as_str());
+        assert_eq!(value["token"]["mint"], mint.pubkey().to_string().as_str());
+        assert_eq!(value["profiles"]["stage"]["token"]["owner"], profile_owner.pubkey().to_string().as_str());
+        assert_eq!(value["profiles"]["stage"]["token"]["mint"], "env:MINT");
+        assert_eq!(value["seed"]["phrase"], REDACTED);
+        assert_eq!(value["seed"]["passphrase"], REDACTED);
+        let values = strings(&value);
+        for secret in secrets.iter().map(String::as_str).chain(["secret words", "secret pass"]) {
+            assert!(!values.iter().any(|value| value.contains(secret)), "{secret} is revealed");
+        }
+
+        let revealed = strings(&config.to_value(true).unwrap());
+        for secret in secrets.iter().map(String::as_str).chain(["secret words", "secret pass"]) {
+            assert!(revealed.iter().any(|value| value == secret), "{secret} is not revealed");
+        }
+    }
+
+    #[test]
+    fn debug_output_has_no_secrets() {
+        let kp = new_keypair();
+        let config = config(&config_yaml(&format!("\n  - {kp}"), " {}")).unwrap();
+        let seed = SeedConfig { phrase: "secret words".into(), passphrase: "secret pass".into() };
+        let debug = format!("{config:?} {config:#?} {seed:?} {:?}", config.token_keypairs());
+        assert!(debug.contains(&kp.pubkey().to_string()));
+        for secret in [kp.to_string(), config.token_keypairs().owner.to_string(), "secret".to_string()] {
+            assert!(!debug.contains(&secret), "{secret} is in the debug output");
+        }
+    }
+
+    #[test]
+    fn names_taken_for_indices_or_pubkeys_are_rejected() {
+        let wallet = |name: &str| format!("\n  - {{ name: \"{name}\", keypair: {} }}", new_keypair());
+        let pubkey = "11111111111111111111111111111111";
+        assert!(config(&config_yaml(&wallet("alice"), " {}")).is_ok());
+        for name in ["7", pubkey] {
+            assert!(matches!(
+                config(&config_yaml(&wallet(name), " {}")),
+                Err(ConfigError::AmbiguousName { name: n }) if n == name,
+            ));
+            assert!(matches!(
+                config(&config_yaml(" []", &format!("\n  \"{name}\": {pubkey}"))),
+                Err(ConfigError::AmbiguousName { name: n }) if n == name,
+            ));
+        }
+    }
+
+    #[test]
+    fn memos_are_unique_by_run_and_case() {
+        let case = |memo: Option<&str>| TestTransferConfig {
+            from: AddressRef::Index(0),
+            to: AddressRef::Index(1),
+            amount: 1.0,
+            memo: memo.map(String::from),
+        };
+        assert_eq!(case(None).render_memo("r1", 3), "Test transfer r1#3");
+        assert_eq!(case(Some("{case} of {run_id}")).render_memo("r1", 3), "3 of r1");
+        assert_eq!(case(Some("fixed")).render_memo("r1", 3), "fixed [r1#3]");
+        assert_eq!(case(Some("run {run_id}")).render_memo("r1", 3), "run r1 [r1#3]");
+        assert_eq!(case(Some("")).render_memo("r1", 3), " [r1#3]");
+    }
+
+    const ABANDON_PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn derivation_matches_solana_tools() {
+        // `solana-keygen recover 'prompt://?key=0/0'` of the phrase with no passphrase
+        let seed = SeedConfig { phrase: ABANDON_PHRASE.into(), passphrase: String::new() }.to_seed().unwrap();
+        assert_eq!(derive_keypair(&seed, 0).unwrap().pubkey().to_string(), "HAgk14JpMQLgt6rVgv7cBQFJWFto5Dqxi472uT3DKpqk");
+    }
+
+    #[test]
+    fn derivation_index_must_be_hardened() {
+        let source = serde_yaml::from_str::<KeypairSource>("{ derivation_index: 2147483647 }").unwrap();
+        assert!(matches!(source, KeypairSource::Derived { derivation_index: 0x7fff_ffff }));
+        assert!(serde_yaml::from_str::<KeypairSource>("{ derivation_index: 2147483648 }").is_err());
+        assert!(matches!(
+            derive_keypair(&[0; 64], DERIVATION_INDEX_LIMIT),
+            Err(ConfigError::DerivationIndexOutOfRange { index: DERIVATION_INDEX_LIMIT }),
+        ));
+    }
+
+    #[test]
+    fn starter_config_is_valid() {
+        let rpc_url = url::Url::parse("http://localhost:8899").unwrap();
+        for wallet_count in [0, 1, 3, 4] {
+            let yaml = starter_config_yaml(wallet_count, &rpc_url).unwrap();
+            let path = std::env::temp_dir().join(format!("config-test-starter-{wallet_count}-{}.yaml", std::process::id()));
+            std::fs::write(&path, &yaml).unwrap();
+            let issues = Config::validate_file(&path);
+            std::fs::remove_file(&path).unwrap();
+            let issues = issues.unwrap().into_iter().map(|issue| format!("{}: {}", issue.path, issue.msg)).collect::<Vec<_>>();
+            assert!(issues.is_empty(), "{wallet_count} wallets: {issues:?}\n{yaml}");
+
+            let config = config(&yaml).unwrap();
+            assert_eq!(config.wallets.0.len(), wallet_count);
+            assert_eq!(config.rpc.uri.0, rpc_url);
+            assert_eq!(config.test.mint.0, config.token_keypairs().mint.pubkey().0);
+            assert_eq!(config.test.transfers.sols.len(), wallet_count / 2);
+            assert_eq!(config.test.transfers.tokens.len(), wallet_count / 2);
+        }
+    }
+
+    /// Environment variable name unique to the test process.
+    fn env_var(name: &str) -> String {
+        format!("CONFIG_TEST_{name}_{}", std::process::id())
+    }
+
+    #[test]
+    fn keypair_source_from_str() {
+        let kp = new_keypair();
+        let parsed = |s: &str| KeypairSource::from_str(s).unwrap().to_string();
+        assert_eq!(parsed("env:WALLET_KEY"), "env:WALLET_KEY");
+        assert_eq!(parsed("file:wallet"), "file:wallet");
+        assert_eq!(parsed("keystore:2"), "keystore:2");
+        // paths don't need the prefix
+        for path in ["./wallet.json", "keys/wallet", "wallet.json", "C:\\keys\\wallet"] {
+            assert_eq!(parsed(path), format!("file:{path}"));
+        }
+        assert!(matches!(
+            KeypairSource::from_str(&kp.to_string()),
+            Ok(KeypairSource::Inline(inline)) if inline.pubkey().0 == kp.pubkey().0,
+        ));
+        for invalid in ["keystore:first", "keystore:-1", "wallet", ""] {
+            assert!(matches!(KeypairSource::from_str(invalid), Err(ConfigError::InvalidKeypair { .. })), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn keypair_source_load() {
+        let kp = new_keypair();
+        let dir = std::env::temp_dir().join(format!("config-test-keypairs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("wallet.json"), serde_json::to_string(&kp.0.to_bytes().to_vec()).unwrap()).unwrap();
+        std::fs::write(dir.join("invalid.json"), "not json").unwrap();
+        std::fs::write(dir.join("short.json"), "[1, 2, 3]").unwrap();
+        let load = |source: &str| KeypairSource::from_str(source).unwrap().load(&dir);
+        let loaded = load("wallet.json");
+        let missing = load("missing.json");
+        let invalid = load("invalid.json");
+        let short = load("short.json");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.unwrap().pubkey().0, kp.pubkey().0);
+        assert!(matches!(missing, Err(ConfigError::ReadFailed { path, .. }) if path.ends_with("missing.json")));
+        assert!(matches!(invalid, Err(ConfigError::KeypairFileParseFailed { path, .. }) if path.ends_with("invalid.json")));
+        assert!(matches!(short, Err(ConfigError::InvalidKeypair { source_name, .. }) if source_name.ends_with("short.json")));
+        assert_eq!(load(&kp.to_string()).unwrap().pubkey().0, kp.pubkey().0);
+    }
+
+    #[test]
+    fn keypair_source_load_env() {
+        let kp = new_keypair();
+        let (base58_var, json_var, invalid_var, unset_var) = (env_var("BASE58"), env_var("JSON"), env_var("INVALID"), env_var("UNSET"));
+        std::env::set_var(&base58_var, format!(" {kp}\n"));
+        std::env::set_var(&json_var, serde_json::to_string(&kp.0.to_bytes().to_vec()).unwrap());
+        std::env::set_var(&invalid_var, "[1, 2");
+        let load = |var: &str| KeypairSource::Env(var.to_string()).load(Path::new(""));
+        assert_eq!(load(&base58_var).unwrap().pubkey().0, kp.pubkey().0);
+        assert_eq!(load(&json_var).unwrap().pubkey().0, kp.pubkey().0);
+        assert!(matches!(load(&invalid_var), Err(ConfigError::InvalidKeypair { source_name, .. }) if source_name == format!("env:{invalid_var}")));
+        assert!(matches!(load(&unset_var), Err(ConfigError::KeypairEnvVarError { var, .. }) if var == unset_var));
+        for var in [base58_var, json_var, invalid_var] {
+            std::env::remove_var(var);
+        }
+    }
+
+    #[test]
+    fn wallet_source_labels() {
+        let (file_kp, env_kp) = (new_keypair(), new_keypair());
+        let dir = std::env::temp_dir().join(format!("config-test-labels-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let wallet_path = dir.join("wallet.json");
+        std::fs::write(&wallet_path, serde_json::to_string(&file_kp.0.to_bytes().to_vec()).unwrap()).unwrap();
+        let var = env_var("LABELS");
+        std::env::set_var(&var, env_kp.to_string());
+        let wallets = format!(
+            "\n  - {}\n  - {}\n  - {{ name: carol, tags: [t1, t2], keypair: env:{var} }}\n  - {{ derivation_index: 7 }}",
+            new_keypair(), wallet_path.to_string_lossy(),
+        );
+        let config = config(&format!("{}seed: {{ phrase: {ABANDON_PHRASE} }}\n", config_yaml(&wallets, " {}")));
+        std::env::remove_var(&var);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let config = config.unwrap();
+        let labels = config.wallet_labels.iter()
+            .map(|label| (label.origin.clone(), label.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(labels, [
+            ("inline".to_string(), String::new()),
+            (format!("file:{}", wallet_path.to_string_lossy()), String::new()),
+            (format!("env:{var}"), "carol #t1 #t2".to_string()),
+            ("derived:m/44'/501'/7'/0'".to_string(), String::new()),
+        ]);
+        assert_eq!(config.wallets.0[1].pubkey().0, file_kp.pubkey().0);
+        assert_eq!(config.wallets.0[2].pubkey().0, env_kp.pubkey().0);
+    }
 }
@@ -0,0 +1,413 @@
+use std::path::{Path, PathBuf};
+use snafu::{ResultExt, Snafu};
+
+/// Line based editor of top-level sections of the YAML config file.
+/// Unlike re-serialization with serde_yaml it keeps comments, order and formatting of untouched sections.
+pub(crate) struct ConfigFile {
+    path: PathBuf,
+    lines: Vec<String>,
+}
+
+impl ConfigFile {
+    pub(crate) fn read(path: &Path) -> ConfigFileResult<Self> {
+        let text = std::fs::read_to_string(path)
+            .context(ReadFailedSnafu { path: path.to_string_lossy() })?;
+        Ok(Self { path: path.to_path_buf(), lines: text.lines().map(String::from).collect() })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the section (or appends it to the end of the file) with `key: value` line.
+    pub(crate) fn set_scalar(&mut self, key: &str, value: &str) {
+        self.replace_section(key, vec![format!("{key}: {value}")]);
+    }
+
+    /// Replaces the section (or appends it to the end of the file) with a block sequence of items
+    /// (already formatted YAML values).
+    pub(crate) fn set_sequence(&mut self, key: &str, items: &[String]) {
+        let section = match items.is_empty() {
+            true => vec![format!("{key}: []")],
+            false => std::iter::once(format!("{key}:"))
+                .chain(items.iter().map(|item| format!("  - {item}")))
+                .collect(),
+        };
+        self.replace_section(key, section);
+    }
+
+    /// Removes the section if it exists.
+    pub(crate) fn remove(&mut self, key: &str) {
+        if let Some(range) = self.section_range(key) {
+            self.lines.drain(range);
+        }
+    }
+
+    /// Appends items (already formatted YAML values) to the top-level sequence section.
+    /// The section is created if it doesn't exist, a flow sequence is converted into a block one.
+    pub(crate) fn append_to_sequence(&mut self, key: &str, items: &[String]) -> ConfigFileResult<()> {
+        let Some(range) = self.section_range(key) else {
+            let mut section = vec![format!("{key}:")];
+            section.extend(items.iter().map(|item| format!("  - {item}")));
+            self.replace_section(key, section);
+            return Ok(())
+        };
+        let header = &self.lines[range.start];
+        let inline_value = header[key.len() + 1..].trim();
+        if !inline_value.is_empty() && !inline_value.starts_with('#') {
+            // flow style, e.g. `wallets: []`, the existing items are kept one per line in the flow style
+            let section_text = self.lines[range.clone()].join("\n");
+            let existing = match serde_yaml::from_str::<serde_yaml::Mapping>(&section_text)
+                .context(ParseFailedSnafu { key })?
+                .remove(key)
+            {
+                Some(serde_yaml::Value::Sequence(existing)) => existing,
+                Some(serde_yaml::Value::Null) | None => Vec::new(),
+                Some(_) => return NotASequenceSnafu { key }.fail(),
+            };
+            let mut section = vec![format!("{key}:")];
+            section.extend(existing.iter().map(|item| format!("  - {}", flow_yaml(item))));
+            section.extend(items.iter().map(|item| format!("  - {item}")));
+            self.lines.splice(range, section);
+            return Ok(())
+        }
+        let item_prefix = self.lines[range.clone()].iter().skip(1)
+            .find_map(|line| {
+                let trimmed = line.trim_start();
+                trimmed.starts_with("- ").then(|| line[..line.len() - trimmed.len()].to_string())
+            })
+            .unwrap_or_else(|| "  ".to_string());
+        let insert_at = range.end;
+        self.lines.splice(
+            insert_at..insert_at,
+            items.iter().map(|item| format!("{item_prefix}- {item}")),
+        );
+        Ok(())
+    }
+
+    /// Removes the item of the top-level block sequence section and returns its text,
+    /// `None` if there is no such item (or the sequence is in the flow style).
+    pub(crate) fn remove_sequence_item(&mut self, key: &str, index: usize) -> Option<String> {
+        let range = self.section_range(key)?;
+        // only the items at the indentation of the first one, nested sequences belong to them
+        let (_, item_lines) = self.find_item(range.start + 1..range.end, index)?;
+        Some(self.lines.drain(item_lines).collect::<Vec<_>>().join("\n"))
+    }
+
+    /// 1-based line number of the value at the YAML path like `test.transfers.sols[3].from`.
+    /// Flow style collections are not looked into, the line of the closest found parent is returned for them.
+    pub(crate) fn line_of(&self, path: &str) -> Option<usize> {
+        let mut range = 0..self.lines.len();
+        let mut found = None;
+        for segment in path.split('.') {
+            let (key, indices) = segment.split_once('[').unwrap_or((segment, ""));
+            let indices = indices.split('[').filter_map(|index| index.trim_end_matches(']').parse::<usize>().ok());
+            let Some((line_no, key_range)) = self.find_key(range.clone(), key) else { break };
+            found = Some(line_no + 1);
+            range = key_range;
+            for index in indices {
+                let Some((line_no, item_range)) = self.find_item(range.clone(), index) else { return found };
+                found = Some(line_no + 1);
+                range = item_range;
+            }
+        }
+        found
+    }
+
+    /// The line of `key:` mapping entry among the least indented ones and the range of its nested lines.
+    fn find_key(&self, range: std::ops::Range<usize>, key: &str) -> Option<(usize, std::ops::Range<usize>)> {
+        let entries = self.content_lines(range.clone()).map(|(line_no, column, content)| {
+            // the first entry of a block sequence item is on the item line: `- key: value`
+            let stripped = content.trim_start_matches("- ");
+            (line_no, column + content.len() - stripped.len(), stripped)
+        }).collect::<Vec<_>>();
+        let column = entries.iter().map(|(_, column, _)| *column).min()?;
+        let header = format!("{key}:");
+        let (line_no, _, _) = entries.iter().find(|(_, c, content)| *c == column && content.starts_with(&header))?;
+        let end = entries.iter()
+            .find(|(other, c, _)| other > line_no && *c <= column)
+            .map_or(range.end, |(other, _, _)| *other);
+        Some((*line_no, line_no + 1..end))
+    }
+
+    /// The line of the block sequence item and the range of its lines (including the item line).
+    /// Items are the lines at the indentation of the first one.
+    fn find_item(&self, range: std::ops::Range<usize>, index: usize) -> Option<(usize, std::ops::Range<usize>)> {
+        let lines = self.content_lines(range.clone()).collect::<Vec<_>>();
+        let (_, column, _) = lines.iter().find(|(_, _, content)| content.starts_with('-'))?;
+        let column = *column;
+        let (start, _, _) = lines.iter()
+            .filter(|(_, c, content)| *c == column && content.starts_with('-'))
+            .nth(index)?;
+        let end = lines.iter()
+            .find(|(other, c, _)| other > start && *c <= column)
+            .map_or(range.end, |(other, _, _)| *other);
+        Some((*start, *start..end))
+    }
+
+    /// Non-blank non-comment lines of the range with their indentation and content.
+    fn content_lines(&self, range: std::ops::Range<usize>) -> impl Iterator<Item = (usize, usize, &str)> {
+        self.lines[range.clone()].iter().zip(range)
+            .map(|(line, line_no)| {
+                let content = line.trim_start();
+                (line_no, line.len() - content.len(), content)
+            })
+            .filter(|(_, _, content)| !content.is_empty() && !content.starts_with('#'))
+    }
+
+    /// Writes the file, the previous version is kept as `<name>.bak`.
+    pub(crate) fn save_with_backup(&self) -> ConfigFileResult<PathBuf> {
+        let mut backup_name = self.path.file_name().unwrap_or_default().to_os_string();
+        backup_name.push(".bak");
+        let backup_path = self.path.with_file_name(backup_name);
+        std::fs::copy(&self.path, &backup_path)
+            .context(WriteFailedSnafu { path: backup_path.to_string_lossy() })?;
+        self.save()?;
+        Ok(backup_path)
+    }
+
+    /// Writes the file atomically (via a temp file) with the permissions of the original one, no backup is made.
+    pub(crate) fn save(&self) -> ConfigFileResult<()> {
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| {
+                crate::wallet::write_file_atomically(&self.path, text.as_bytes(), Some(metadata.permissions()))
+            })
+            .context(WriteFailedSnafu { path: self.path.to_string_lossy() })
+    }
+
+    fn replace_section(&mut self, key: &str, section: Vec<String>) {
+        match self.section_range(key) {
+            Some(range) => { self.lines.splice(range, section); }
+            None => self.lines.extend(section),
+        }
+    }
+
+    /// Lines of the top-level `key:` section: the key line and all the following indented lines,
+    /// sequence items and comments (comments and blank lines at the end belong to the next section).
+    fn section_range(&self, key: &str) -> Option<std::ops::Range<usize>> {
+        let header = format!("{key}:");
+        let start = self.lines.iter().position(|line| line.starts_with(&header))?;
+        let mut end = start + 1;
+        for (line_no, line) in self.lines.iter().enumerate().skip(start + 1) {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') { continue }
+            if line.starts_with(' ') || line.starts_with('\t') || line.starts_with("- ") {
+                end = line_no + 1;
+                continue
+            }
+            break
+        }
+        Some(start..end)
+    }
+}
+
+/// One line flow style YAML of the value, so a mapping or a sequence fits a block sequence item line.
+fn flow_yaml(value: &serde_yaml::Value) -> String {
+    use serde_yaml::Value;
+    match value {
+        Value::Sequence(items) => format!("[{}]", items.iter().map(flow_yaml).collect::<Vec<_>>().join(", ")),
+        Value::Mapping(entries) if entries.is_empty() => "{}".to_string(),
+        Value::Mapping(entries) => format!(
+            "{{ {} }}",
+            entries.iter().map(|(k, v)| format!("{}: {}", flow_yaml(k), flow_yaml(v))).collect::<Vec<_>>().join(", "),
+        ),
+        Value::Tagged(tagged) => format!("{} {}", tagged.tag, flow_yaml(&tagged.value)),
+        Value::String(s) => {
+            // plain only if it can't break the flow syntax and is read back as the same string
+            let plain = !s.is_empty()
+                && s.chars().all(|c| c.is_alphanumeric() || "-_./".contains(c))
+                && serde_yaml::from_str::<Value>(s).ok().as_ref() == Some(value);
+            match plain {
+                true => s.clone(),
+                false => serde_json::to_string(s).expect("string is serializable"),
+            }
+        }
+        scalar => serde_yaml::to_string(scalar).expect("scalar is serializable").trim_end().to_string(),
+    }
+}
+
+pub(crate) type ConfigFileResult<T> = Result<T, ConfigFileError>;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub(crate) enum ConfigFileError {
+    #[snafu(display("Can't read config file: path: {path}; cause: {source}"))]
+    ReadFailed { path: String, source: std::io::Error },
+    #[snafu(display("Can't write config file: path: {path}; cause: {source}"))]
+    WriteFailed { path: String, source: std::io::Error },
+    #[snafu(display("Can't parse config file section: key: {key}; cause: {source}"))]
+    ParseFailed { key: String, source: serde_yaml::Error },
+    #[snafu(display("Config file section is not a sequence: key: {key}"))]
+    NotASequence { key: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_file(text: &str) -> ConfigFile {
+        ConfigFile { path: PathBuf::from("config.yaml"), lines: text.lines().map(String::from).collect() }
+    }
+
+    fn text(file: &ConfigFile) -> String {
+        file.lines.join("\n")
+    }
+
+    const CONFIG: &str = "\
+# header
+rpc:
+  url: http://localhost:8899
+wallets:
+  # first
+  - name: a
+    tags:
+      - x
+      - y
+  - b
+  - name: c
+    keypair: keystore:0
+test:
+  transfers:
+    sols:
+      - from: a
+        to: b
+      - from: b
+        to: c";
+
+    #[test]
+    fn remove_sequence_item_skips_nested_items() {
+        let mut file = config_file(CONFIG);
+        assert_eq!(file.remove_sequence_item("wallets", 1).as_deref(), Some("  - b"));
+        assert_eq!(
+            file.remove_sequence_item("wallets", 1).as_deref(),
+            Some("  - name: c\n    keypair: keystore:0"),
+        );
+        assert_eq!(file.remove_sequence_item("wallets", 1), None);
+        assert_eq!(
+            file.remove_sequence_item("wallets", 0).as_deref(),
+            Some("  - name: a\n    tags:\n      - x\n      - y"),
+        );
+        assert!(text(&file).contains("wallets:\n  # first\ntest:\n"));
+    }
+
+    #[test]
+    fn remove_sequence_item_at_zero_indent() {
+        let mut file = config_file("wallets:\n- a\n- b:\n  - c\nrpc: {}");
+        assert_eq!(file.remove_sequence_item("wallets", 1).as_deref(), Some("- b:\n  - c"));
+        assert_eq!(text(&file), "wallets:\n- a\nrpc: {}");
+    }
+
+    #[test]
+    fn remove_sequence_item_of_flow_sequence() {
+        let mut file = config_file("wallets: [a, b]");
+        assert_eq!(file.remove_sequence_item("wallets", 0), None);
+        assert_eq!(file.remove_sequence_item("missing", 0), None);
+    }
+
+    #[test]
+    fn append_to_block_sequence() {
+        let mut file = config_file("wallets:\n    - a\nrpc: {}");
+        file.append_to_sequence("wallets", &["b".to_string()]).unwrap();
+        assert_eq!(text(&file), "wallets:\n    - a\n    - b\nrpc: {}");
+    }
+
+    #[test]
+    fn append_to_flow_sequence() {
+        let mut file = config_file("wallets: [a]\nrpc: {}");
+        file.append_to_sequence("wallets", &["b".to_string()]).unwrap();
+        assert_eq!(text(&file), "wallets:\n  - a\n  - b\nrpc: {}");
+    }
+
+    #[test]
+    fn append_to_flow_sequence_of_mappings() {
+        let owner = solana_sdk::signature::Keypair::new().to_base58_string();
+        let (k1, k2, k3) = (
+            solana_sdk::signature::Keypair::new().to_base58_string(),
+            solana_sdk::signature::Keypair::new().to_base58_string(),
+            solana_sdk::signature::Keypair::new().to_base58_string(),
+        );
+        let mut file = config_file(&format!("\
+rpc:
+  uri: http://localhost:8899
+token: {{ owner: {owner}, mint: {owner} }}
+test: {{ mint: 11111111111111111111111111111111, transfers: {{ sols: [], tokens: [] }} }}
+wallets: [{{ name: alice, tags: [a, \"b, c\", \"true\"], keypair: {k1} }},
+  {k2}, \"env:KEY\"]  # trailing comment
+address_book: {{}}"));
+        file.append_to_sequence("wallets", std::slice::from_ref(&k3)).unwrap();
+        assert_eq!(text(&file).lines().filter(|line| line.starts_with("  - ")).count(), 4);
+        let config = serde_yaml::from_str::<crate::config::Config>(&text(&file)).expect("valid config");
+        let wallets = config.wallet_sources.iter()
+            .map(|entry| (entry.name.clone(), entry.tags.clone(), entry.source.to_yaml_item()))
+            .collect::<Vec<_>>();
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(wallets, [
+            (Some("alice".to_string()), strings(&["a", "b, c", "true"]), k1),
+            (None, vec![], k2),
+            (None, vec![], "env:KEY".to_string()),
+            (None, vec![], k3),
+        ]);
+    }
+
+    #[test]
+    fn append_to_invalid_flow_sequence() {
+        for yaml in ["wallets: [a, b\nrpc: {}", "wallets: { a: b }\nrpc: {}", "wallets: a\nrpc: {}"] {
+            let mut file = config_file(yaml);
+            assert!(file.append_to_sequence("wallets", &["c".to_string()]).is_err(), "{yaml}");
+            // the existing items are never dropped
+            assert_eq!(text(&file), yaml);
+        }
+        let mut file = config_file("wallets: ~\nrpc: {}");
+        file.append_to_sequence("wallets", &["c".to_string()]).unwrap();
+        assert_eq!(text(&file), "wallets:\n  - c\nrpc: {}");
+    }
+
+    #[test]
+    fn append_to_missing_sequence() {
+        let mut file = config_file("rpc: {}");
+        file.append_to_sequence("wallets", &["a".to_string()]).unwrap();
+        assert_eq!(text(&file), "rpc: {}\nwallets:\n  - a");
+    }
+
+    #[test]
+    fn set_and_remove_sections() {
+        let mut file = config_file(CONFIG);
+        file.set_sequence("wallets", &[]);
+        file.set_scalar("keystore", "ks.json");
+        file.remove("rpc");
+        assert!(text(&file).starts_with("# header\nwallets: []\ntest:\n"));
+        assert!(text(&file).ends_with("to: c\nkeystore: ks.json"));
+        file.set_sequence("wallets", &["a".to_string(), "b".to_string()]);
+        assert!(text(&file).starts_with("# header\nwallets:\n  - a\n  - b\ntest:\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt as _;
+        let path = std::env::temp_dir().join(format!("config-file-test-save-{}.yaml", std::process::id()));
+        std::fs::write(&path, "rpc: {}\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let mut file = ConfigFile::read(&path).unwrap();
+        file.set_scalar("keystore", "ks.json");
+        file.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "rpc: {}\nkeystore: ks.json\n");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn line_of_paths() {
+        let file = config_file(CONFIG);
+        assert_eq!(file.line_of("rpc.url"), Some(3));
+        assert_eq!(file.line_of("wallets[0]"), Some(6));
+        assert_eq!(file.line_of("wallets[0].tags[1]"), Some(9));
+        assert_eq!(file.line_of("wallets[2].keypair"), Some(12));
+        assert_eq!(file.line_of("test.transfers.sols[1].to"), Some(19));
+        // the closest found parent
+        assert_eq!(file.line_of("wallets[5]"), Some(4));
+        assert_eq!(file.line_of("test.missing"), Some(13));
+    }
+}
@@ -0,0 +1,363 @@
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+use futures::{StreamExt as _, future::join_all};
+use snafu::Snafu;
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_config::RpcSignatureSubscribeConfig,
+    rpc_response::{ProcessedSignatureResult, Response, RpcSignatureResult},
+};
+use solana_sdk::{
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    signature::Signature,
+    transaction::TransactionError,
+};
+use tokio::{sync::{OnceCell, oneshot}, time::{Instant, sleep}};
+use crate::config::RpcConfig;
+
+/// Max signatures of a `getSignatureStatuses` request.
+const MAX_STATUSES_PER_REQUEST: usize = 256;
+const POLL_INTERVAL: Duration = Duration::from_millis(400);
+/// Finalization takes about 13 seconds after confirmation, a blockhash expires in 60-90 seconds.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Waits for transactions to reach a commitment level by `signatureSubscribe` notifications
+/// racing the shared [`StatusTracker`] polling, which also covers an unavailable websocket endpoint,
+/// a missed notification or a transaction which reached the level before the subscription.
+pub(crate) struct Confirmer {
+    ws_url: url::Url,
+    /// connected on first use, `None` if the websocket endpoint is unreachable
+    pubsub: OnceCell<Option<PubsubClient>>,
+    tracker: Arc<StatusTracker>,
+}
+
+impl Confirmer {
+    pub(crate) fn new(rpc_client: Arc<RpcClient>, config: &RpcConfig) -> Self {
+        Self { ws_url: config.ws_url(), pubsub: OnceCell::new(), tracker: StatusTracker::start(rpc_client) }
+    }
+
+    pub(crate) async fn confirm(&self, signature: &Signature, commitment: CommitmentLevel) -> ConfirmResult<()> {
+        let tracked = self.tracker.track(*signature, commitment);
+        let Some(pubsub) = self.pubsub().await else {
+            return tracked.await
+        };
+        let config = RpcSignatureSubscribeConfig {
+            commitment: Some(CommitmentConfig { commitment }),
+            enable_received_notification: Some(false),
+        };
+        let Ok((mut notifications, unsubscribe)) = pubsub.signature_subscribe(signature, Some(config)).await else {
+            return tracked.await
+        };
+        let notified = async {
+            match notifications.next().await {
+                Some(Response { value: RpcSignatureResult::ProcessedSignature(ProcessedSignatureResult { err }), .. }) => status_result(err),
+                // the websocket is closed, it's up to the tracker
+                _ => std::future::pending().await,
+            }
+        };
+        let result = tokio::select! {
+            result = tracked => result,
+            result = notified => result,
+        };
+        drop(notifications);
+        unsubscribe().await;
+        result
+    }
+
+    /// Results in the order of `signatures`.
+    pub(crate) async fn confirm_all(&self, signatures: &[Signature], commitment: CommitmentLevel) -> Vec<ConfirmResult<()>> {
+        join_all(signatures.iter().map(|signature| self.confirm(signature, commitment))).await
+    }
+
+    async fn pubsub(&self) -> Option<&PubsubClient> {
+        self.pubsub.get_or_init(|| async {
+            match PubsubClient::new(self.ws_url.as_str()).await {
+                Ok(pubsub) => Some(pubsub),
+                Err(e) => {
+                    eprintln!("websocket {} is unavailable, polling transaction statuses: {e}", self.ws_url);
+                    None
+                }
+            }
+        }).await.as_ref()
+    }
+}
+
+/// Collects signatures awaited by all the jobs of a run and polls their statuses together
+/// in batches of up to [`MAX_STATUSES_PER_REQUEST`] signatures, one batch round per [`POLL_INTERVAL`].
+pub(crate) struct StatusTracker {
+    rpc_client: Arc<RpcClient>,
+    pending: std::sync::Mutex<HashMap<Signature, Vec<Waiter>>>,
+}
+
+struct Waiter {
+    commitment: CommitmentLevel,
+    deadline: Instant,
+    sender: oneshot::Sender<ConfirmResult<()>>,
+}
+
+impl StatusTracker {
+    /// Starts polling in background until the tracker is dropped.
+    pub(crate) fn start(rpc_client: Arc<RpcClient>) -> Arc<Self> {
+        let tracker = Arc::new(Self { rpc_client, pending: Default::default() });
+        let weak = Arc::downgrade(&tracker);
+        tokio::spawn(async move {
+            loop {
+                sleep(POLL_INTERVAL).await;
+                let Some(tracker) = weak.upgrade() else { break };
+                tracker.poll().await;
+            }
+        });
+        tracker
+    }
+
+    /// Resolves when the transaction reaches `commitment`, fails or isn't found in time.
+    pub(crate) fn track(&self, signature: Signature, commitment: CommitmentLevel) -> impl Future<Output = ConfirmResult<()>> {
+        let (sender, receiver) = oneshot::channel();
+        let deadline = Instant::now() + CONFIRMATION_TIMEOUT;
+        self.pending.lock().expect("not poisoned")
+            .entry(signature).or_default()
+            .push(Waiter { commitment, deadline, sender });
+        async move {
+            receiver.await.unwrap_or_else(|_| ConfirmationTimeoutSnafu { signature, commitment }.fail())
+        }
+    }
+
+    async fn poll(&self) {
+        let signatures = {
+            let mut pending = self.pending.lock().expect("not poisoned");
+            let now = Instant::now();
+            pending.retain(|signature, waiters| {
+                resolve(waiters, |waiter| waiter.sender.is_closed().then_some(None));
+                resolve(waiters, |waiter| (waiter.deadline <= now).then(|| Some(ConfirmationTimeoutSnafu {
+                    signature: *signature,
+                    commitment: waiter.commitment,
+                }.fail())));
+                !waiters.is_empty()
+            });
+            pending.keys().copied().collect::<Vec<_>>()
+        };
+        for batch in signatures.chunks(MAX_STATUSES_PER_REQUEST) {
+            // failed requests are retried by the next round
+            let Ok(response) = self.rpc_client.get_signature_statuses(batch).await else { continue };
+            let mut pending = self.pending.lock().expect("not poisoned");
+            for (signature, status) in batch.iter().zip(response.value) {
+                let (Some(status), Some(waiters)) = (status, pending.get_mut(signature)) else { continue };
+                resolve(waiters, |waiter| {
+                    status.satisfies_commitment(CommitmentConfig { commitment: waiter.commitment })
+                        .then(|| Some(status_result(status.err.clone())))
+                });
+                if waiters.is_empty() {
+                    pending.remove(signature);
+                }
+            }
+        }
+    }
+}
+
+/// Removes waiters `outcome` returns `Some` for, sending them the result if there is one.
+fn resolve(waiters: &mut Vec<Waiter>, mut outcome: impl FnMut(&Waiter) -> Option<Option<ConfirmResult<()>>>) {
+    let mut i = 0;
+    while i < waiters.len() {
+        match outcome(&waiters[i]) {
+            Some(result) => {
+                let waiter = waiters.swap_remove(i);
+                if let Some(result) = result {
+                    let _ = waiter.sender.send(result);
+                }
+            }
+            None => i += 1,
+        }
+    }
+}
+
+fn status_result(err: Option<TransactionError>) -> ConfirmResult<()> {
+    match err {
+        Some(source) => Err(ConfirmError::TransactionFailed { source }),
+        None => Ok(()),
+    }
+}
+
+pub(crate) type ConfirmResult<T, E = ConfirmError> = Result<T, E>;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub(crate) enum ConfirmError {
+    #[snafu(display("transaction failed: {source}"))]
+    TransactionFailed { source: TransactionError },
+    #[snafu(display("transaction {signature} is not {commitment} after {} seconds", CONFIRMATION_TIMEOUT.as_secs()))]
+    ConfirmationTimeout { signature: Signature, commitment: CommitmentLevel },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use async_trait::async_trait;
+    use futures::FutureExt as _;
+    use solana_client::{
+        client_error::Result as ClientResult,
+        rpc_client::RpcClientConfig,
+        rpc_request::RpcRequest,
+        rpc_sender::{RpcSender, RpcTransportStats},
+    };
+
+    /// `getSignatureStatuses` responder with the statuses set by the test, records the sizes of the requests.
+    #[derive(Default)]
+    struct MockCluster {
+        statuses: Mutex<HashMap<String, serde_json::Value>>,
+        requests: Mutex<Vec<usize>>,
+    }
+
+    struct MockSender(Arc<MockCluster>);
+
+    #[async_trait]
+    impl RpcSender for MockSender {
+        async fn send(&self, request: RpcRequest, params: serde_json::Value) -> ClientResult<serde_json::Value> {
+            assert_eq!(request, RpcRequest::GetSignatureStatuses);
+            let signatures = params[0].as_array().expect("signatures");
+            self.0.requests.lock().expect("not poisoned").push(signatures.len());
+            let statuses = self.0.statuses.lock().expect("not poisoned");
+            let value = signatures.iter()
+                .map(|signature| statuses.get(signature.as_str().expect("base58 signature")).cloned().unwrap_or_default())
+                .collect::<Vec<_>>();
+            Ok(serde_json::json!({ "context": { "slot": 1 }, "value": value }))
+        }
+
+        fn get_transport_stats(&self) -> RpcTransportStats {
+            RpcTransportStats::default()
+        }
+
+        fn url(&self) -> String {
+            "mock".into()
+        }
+    }
+
+    impl MockCluster {
+        fn set_status(&self, signature: &Signature, level: &str, err: Option<TransactionError>) {
+            let status = serde_json::json!({
+                "slot": 1,
+                // `null` stands for a rooted (finalized) transaction
+                "confirmations": if level == "finalized" { None } else { Some(1) },
+                "err": err,
+                "status": match &err { Some(err) => serde_json::json!({ "Err": err }), None => serde_json::json!({ "Ok": null }) },
+                "confirmationStatus": level,
+            });
+            self.statuses.lock().expect("not poisoned").insert(signature.to_string(), status);
+        }
+
+        fn take_requests(&self) -> Vec<usize> {
+            std::mem::take(&mut *self.requests.lock().expect("not poisoned"))
+        }
+    }
+
+    /// The tracker without the background polling, rounds are made by `poll` calls.
+    fn tracker() -> (StatusTracker, Arc<MockCluster>) {
+        let cluster = Arc::new(MockCluster::default());
+        let rpc_client = RpcClient::new_sender(MockSender(cluster.clone()), RpcClientConfig::default());
+        (StatusTracker { rpc_client: Arc::new(rpc_client), pending: Default::default() }, cluster)
+    }
+
+    /// The result if the future is resolved, out of the tokio cooperative budget which would make it pending
+    /// after many polls.
+    fn ready<F: Future + Unpin>(wait: &mut F) -> Option<F::Output> {
+        tokio::task::unconstrained(wait).now_or_never()
+    }
+
+    fn pending_count(tracker: &StatusTracker) -> usize {
+        tracker.pending.lock().expect("not poisoned").len()
+    }
+
+    #[tokio::test]
+    async fn statuses_are_polled_in_batches() {
+        let (tracker, cluster) = tracker();
+        let signatures = (0..600).map(|_| Signature::new_unique()).collect::<Vec<_>>();
+        let mut waits = signatures.iter()
+            .map(|signature| Box::pin(tracker.track(*signature, CommitmentLevel::Confirmed)))
+            .collect::<Vec<_>>();
+        // another waiter of the same signature doesn't add it to the request
+        let mut finalized = Box::pin(tracker.track(signatures[0], CommitmentLevel::Finalized));
+        tracker.poll().await;
+        assert_eq!(cluster.take_requests(), [256, 256, 88]);
+        assert!(waits.iter_mut().all(|wait| ready(wait).is_none()));
+
+        for signature in signatures[..300].iter() {
+            cluster.set_status(signature, "confirmed", None);
+        }
+        tracker.poll().await;
+        assert_eq!(cluster.take_requests(), [256, 256, 88]);
+        for wait in waits[..300].iter_mut() {
+            let result = ready(wait);
+            assert!(matches!(result, Some(Ok(()))), "{result:?}");
+        }
+        assert!(ready(&mut finalized).is_none());
+        assert_eq!(pending_count(&tracker), 301);
+
+        tracker.poll().await;
+        assert_eq!(cluster.take_requests(), [256, 45]);
+    }
+
+    #[tokio::test]
+    async fn waiters_are_resolved_by_their_commitment() {
+        let (tracker, cluster) = tracker();
+        let (signature, failed) = (Signature::new_unique(), Signature::new_unique());
+        let mut processed = Box::pin(tracker.track(signature, CommitmentLevel::Processed));
+        let mut confirmed = Box::pin(tracker.track(signature, CommitmentLevel::Confirmed));
+        let mut finalized = Box::pin(tracker.track(signature, CommitmentLevel::Finalized));
+        let mut failed_wait = Box::pin(tracker.track(failed, CommitmentLevel::Confirmed));
+
+        cluster.set_status(&signature, "processed", None);
+        cluster.set_status(&failed, "confirmed", Some(TransactionError::AccountNotFound));
+        tracker.poll().await;
+        assert!(matches!(ready(&mut processed), Some(Ok(()))));
+        assert!(ready(&mut confirmed).is_none());
+        assert!(matches!(
+            ready(&mut failed_wait),
+            Some(Err(ConfirmError::TransactionFailed { source: TransactionError::AccountNotFound })),
+        ));
+
+        cluster.set_status(&signature, "confirmed", None);
+        tracker.poll().await;
+        assert!(matches!(ready(&mut confirmed), Some(Ok(()))));
+        assert!(ready(&mut finalized).is_none());
+
+        cluster.set_status(&signature, "finalized", None);
+        tracker.poll().await;
+        assert!(matches!(ready(&mut finalized), Some(Ok(()))));
+        assert_eq!(pending_count(&tracker), 0);
+        assert_eq!(cluster.take_requests(), [2, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn dropped_waiters_are_not_polled() {
+        let (tracker, cluster) = tracker();
+        let signature = Signature::new_unique();
+        let wait = tracker.track(signature, CommitmentLevel::Confirmed);
+        let mut other = Box::pin(tracker.track(signature, CommitmentLevel::Finalized));
+        drop(wait);
+        tracker.poll().await;
+        assert_eq!(cluster.take_requests(), [1]);
+        assert!(ready(&mut other).is_none());
+
+        drop(other);
+        tracker.poll().await;
+        assert!(cluster.take_requests().is_empty());
+        assert_eq!(pending_count(&tracker), 0);
+    }
+
+    #[tokio::test]
+    async fn waiters_time_out_at_the_deadline() {
+        let (tracker, cluster) = tracker();
+        let (signature, other) = (Signature::new_unique(), Signature::new_unique());
+        let (sender, receiver) = oneshot::channel();
+        let waiter = Waiter { commitment: CommitmentLevel::Confirmed, deadline: Instant::now(), sender };
+        tracker.pending.lock().expect("not poisoned").insert(signature, vec![waiter]);
+        let mut other_wait = Box::pin(tracker.track(other, CommitmentLevel::Confirmed));
+        tracker.poll().await;
+        assert!(matches!(
+            receiver.now_or_never(),
+            Some(Ok(Err(ConfirmError::ConfirmationTimeout { signature: s, commitment: CommitmentLevel::Confirmed }))) if s == signature,
+        ));
+        // only the signatures still awaited are requested
+        assert_eq!(cluster.take_requests(), [1]);
+        assert!(ready(&mut other_wait).is_none());
+    }
+}
@@ -0,0 +1,241 @@
+use std::path::Path;
+use aes_gcm_siv::{
+    aead::{Aead, KeyInit, OsRng, rand_core::RngCore},
+    Aes256GcmSiv, Nonce,
+};
+use hmac::Hmac;
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use snafu::{ResultExt, Snafu};
+use solana_sdk::bs58;
+use zeroize::Zeroizing;
+use crate::config::WalletEntry;
+
+/// Environment variable holding the keystore password (prompted if absent).
+pub(crate) const PASSWORD_ENV_VAR: &str = "TEST_TASK_KEYSTORE_PASSWORD";
+
+/// Encrypted at rest wallets: PBKDF2-HMAC-SHA256 derived key, AES-256-GCM-SIV encryption.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Keystore {
+    pub(crate) version: u8,
+    pub(crate) kdf: KdfParams,
+    /// base58 encoded nonce
+    pub(crate) nonce: String,
+    /// base58 encoded encrypted json array of wallet entries (base58 encoded keypairs or named ones)
+    pub(crate) ciphertext: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct KdfParams {
+    pub(crate) name: String,
+    pub(crate) iterations: u32,
+    /// base58 encoded salt
+    pub(crate) salt: String,
+}
+
+impl Keystore {
+    const VERSION: u8 = 1;
+    const KDF_NAME: &'static str = "pbkdf2-hmac-sha256";
+    const KDF_ITERATIONS: u32 = 600_000;
+    const SALT_LEN: usize = 16;
+    const NONCE_LEN: usize = 12;
+
+    pub(crate) fn encrypt(wallets: &[WalletEntry], password: &str) -> KeystoreResult<Self> {
+        Self::encrypt_with_iterations(wallets, password, Self::KDF_ITERATIONS)
+    }
+
+    fn encrypt_with_iterations(wallets: &[WalletEntry], password: &str, iterations: u32) -> KeystoreResult<Self> {
+        let mut salt = [0u8; Self::SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; Self::NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let kdf = KdfParams {
+            name: Self::KDF_NAME.to_string(),
+            iterations,
+            salt: bs58::encode(salt).into_string(),
+        };
+        let plaintext = Zeroizing::new(serde_json::to_vec(wallets).context(SerializationSnafu)?);
+        let cipher = Self::cipher(&kdf, password)?;
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| KeystoreError::EncryptionFailed)?;
+        Ok(Self {
+            version: Self::VERSION,
+            kdf,
+            nonce: bs58::encode(nonce).into_string(),
+            ciphertext: bs58::encode(ciphertext).into_string(),
+        })
+    }
+
+    pub(crate) fn decrypt(&self, password: &str) -> KeystoreResult<Vec<WalletEntry>> {
+        if self.version != Self::VERSION {
+            return Err(KeystoreError::UnsupportedFormat { msg: format!("version {}", self.version) });
+        }
+        let nonce = decode_base58("nonce", &self.nonce)?;
+        if nonce.len() != Self::NONCE_LEN {
+            return Err(KeystoreError::UnsupportedFormat { msg: format!("nonce length {}", nonce.len()) });
+        }
+        let ciphertext = decode_base58("ciphertext", &self.ciphertext)?;
+        let cipher = Self::cipher(&self.kdf, password)?;
+        let plaintext = Zeroizing::new(
+            cipher.decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+                .map_err(|_| KeystoreError::DecryptionFailed)?
+        );
+        serde_json::from_slice(&plaintext).context(SerializationSnafu)
+    }
+
+    pub(crate) fn read(path: &Path) -> KeystoreResult<Self> {
+        let path_str = path.to_string_lossy().to_string();
+        let file = std::fs::File::open(path).context(ReadFailedSnafu { path: path_str.clone() })?;
+        serde_json::from_reader(file).context(ParseFailedSnafu { path: path_str })
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> KeystoreResult<()> {
+        let path_str = path.to_string_lossy().to_string();
+        let json = serde_json::to_string_pretty(self).context(SerializationSnafu)?;
+        crate::wallet::write_secret_file_atomically(path, json.as_bytes()).context(WriteFailedSnafu { path: path_str })
+    }
+
+    fn cipher(kdf: &KdfParams, password: &str) -> KeystoreResult<Aes256GcmSiv> {
+        if kdf.name != Self::KDF_NAME {
+            return Err(KeystoreError::UnsupportedFormat { msg: format!("kdf {}", kdf.name) });
+        }
+        let salt = decode_base58("salt", &kdf.salt)?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), &salt, kdf.iterations, key.as_mut_slice());
+        Ok(Aes256GcmSiv::new_from_slice(key.as_slice()).expect("key length is always 32 bytes"))
+    }
+}
+
+/// Reads the keystore password from [`PASSWORD_ENV_VAR`] or prompts it on the terminal.
+/// A new password is prompted twice.
+pub(crate) fn read_password(new: bool) -> KeystoreResult<Zeroizing<String>> {
+    if let Ok(password) = std::env::var(PASSWORD_ENV_VAR) {
+        return Ok(Zeroizing::new(password));
+    }
+    let password = Zeroizing::new(rpassword::prompt_password("Keystore password: ").context(PromptFailedSnafu)?);
+    if new {
+        let confirmation = Zeroizing::new(rpassword::prompt_password("Repeat password: ").context(PromptFailedSnafu)?);
+        if password != confirmation {
+            return Err(KeystoreError::PasswordMismatch);
+        }
+    }
+    Ok(password)
+}
+
+fn decode_base58(field: &str, encoded: &str) -> KeystoreResult<Vec<u8>> {
+    bs58::decode(encoded).into_vec()
+        .map_err(|e| KeystoreError::UnsupportedFormat { msg: format!("{field}: {e}") })
+}
+
+pub(crate) type KeystoreResult<T> = Result<T, KeystoreError>;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub(crate) enum KeystoreError {
+    #[snafu(display("Can't read keystore: path: {path}; cause: {source}"))]
+    ReadFailed { path: String, source: std::io::Error },
+    #[snafu(display("Can't write keystore: path: {path}; cause: {source}"))]
+    WriteFailed { path: String, source: std::io::Error },
+    #[snafu(display("Can't parse keystore: path: {path}; cause: {source}"))]
+    ParseFailed { path: String, source: serde_json::Error },
+    #[snafu(display("Keystore serialization failed: {source}"))]
+    SerializationError { source: serde_json::Error },
+    #[snafu(display("Unsupported keystore format: {msg}"))]
+    UnsupportedFormat { msg: String },
+    #[snafu(display("Can't read password: {source}"))]
+    PromptFailed { source: std::io::Error },
+    #[snafu(display("Passwords do not match"))]
+    PasswordMismatch,
+    #[snafu(display("Keystore encryption failed"))]
+    EncryptionFailed,
+    #[snafu(display("Keystore decryption failed (wrong password or corrupted keystore)"))]
+    DecryptionFailed,
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::signature::Keypair;
+    use crate::config::{KeypairSerde, KeypairSource};
+    use super::*;
+
+    /// Fast enough key derivation for tests, the file keeps the iterations anyway.
+    const ITERATIONS: u32 = 1_000;
+
+    fn wallets() -> Vec<WalletEntry> {
+        vec![
+            WalletEntry::from(KeypairSource::Inline(KeypairSerde(Keypair::new()))),
+            WalletEntry {
+                name: Some("alice".into()),
+                tags: vec!["a".into()],
+                source: KeypairSource::Inline(KeypairSerde(Keypair::new())),
+            },
+        ]
+    }
+
+    fn items(wallets: &[WalletEntry]) -> Vec<String> {
+        wallets.iter().map(|entry| entry.to_yaml_item()).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let wallets = wallets();
+        let keystore = Keystore::encrypt_with_iterations(&wallets, "secret", ITERATIONS).unwrap();
+        assert_eq!(items(&keystore.decrypt("secret").unwrap()), items(&wallets));
+        // no keypair is stored in plaintext
+        let json = serde_json::to_string(&keystore).unwrap();
+        for entry in &wallets {
+            let KeypairSource::Inline(kp) = &entry.source else { unreachable!("inline test wallets") };
+            assert!(!json.contains(&kp.0.to_base58_string()));
+        }
+    }
+
+    #[test]
+    fn wrong_password() {
+        let keystore = Keystore::encrypt_with_iterations(&wallets(), "secret", ITERATIONS).unwrap();
+        assert!(matches!(keystore.decrypt("Secret"), Err(KeystoreError::DecryptionFailed)));
+        assert!(matches!(keystore.decrypt(""), Err(KeystoreError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn tampered_ciphertext() {
+        let keystore = Keystore::encrypt_with_iterations(&wallets(), "secret", ITERATIONS).unwrap();
+        let mut ciphertext = bs58::decode(&keystore.ciphertext).into_vec().unwrap();
+        ciphertext[0] ^= 1;
+        let tampered = Keystore { ciphertext: bs58::encode(&ciphertext).into_string(), ..keystore.clone() };
+        assert!(matches!(tampered.decrypt("secret"), Err(KeystoreError::DecryptionFailed)));
+        let mut nonce = bs58::decode(&keystore.nonce).into_vec().unwrap();
+        nonce[0] ^= 1;
+        let tampered = Keystore { nonce: bs58::encode(&nonce).into_string(), ..keystore.clone() };
+        assert!(matches!(tampered.decrypt("secret"), Err(KeystoreError::DecryptionFailed)));
+        let truncated = Keystore { nonce: bs58::encode(&nonce[1..]).into_string(), ..keystore };
+        assert!(matches!(truncated.decrypt("secret"), Err(KeystoreError::UnsupportedFormat { .. })));
+    }
+
+    #[test]
+    fn kdf_params_are_read_from_file() {
+        let path = std::env::temp_dir().join(format!("keystore-test-{}.json", std::process::id()));
+        let wallets = wallets();
+        let keystore = Keystore::encrypt_with_iterations(&wallets, "secret", ITERATIONS).unwrap();
+        keystore.write(&path).unwrap();
+        let read = Keystore::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+        assert_eq!(read.version, Keystore::VERSION);
+        assert_eq!(read.kdf.name, Keystore::KDF_NAME);
+        assert_eq!(read.kdf.iterations, ITERATIONS);
+        assert_eq!(read.kdf.salt, keystore.kdf.salt);
+        assert_eq!(bs58::decode(&read.kdf.salt).into_vec().unwrap().len(), Keystore::SALT_LEN);
+        // the key is derived with the file parameters, not the current defaults
+        assert_eq!(items(&read.decrypt("secret").unwrap()), items(&wallets));
+        let other_kdf = Keystore { kdf: KdfParams { iterations: ITERATIONS + 1, ..read.kdf.clone() }, ..read.clone() };
+        assert!(matches!(other_kdf.decrypt("secret"), Err(KeystoreError::DecryptionFailed)));
+        let unknown_kdf = Keystore { kdf: KdfParams { name: "scrypt".into(), ..read.kdf.clone() }, ..read };
+        assert!(matches!(unknown_kdf.decrypt("secret"), Err(KeystoreError::UnsupportedFormat { .. })));
+    }
+
+    #[test]
+    fn default_iterations() {
+        let keystore = Keystore::encrypt(&[], "secret").unwrap();
+        assert_eq!(keystore.kdf.iterations, Keystore::KDF_ITERATIONS);
+    }
+}
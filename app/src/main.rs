@@ -6,30 +6,30 @@ use clap::{Parser as _, CommandFactory as _};
 
 mod cli;
 mod config;
+mod config_file;
 mod cmd;
 mod wallet;
 mod token;
 mod worker;
+mod keystore;
+mod rpc;
+mod confirm;
+mod blockhash;
+mod sender;
 
 use cli::{Cli, SubCmd};
-use crate::cli::{TestSubCmd, TestTransferSubCmd, TokenSubCmd, WalletSubCmd};
+use crate::cli::{ConfigSubCmd, TestSubCmd, TestTransferSubCmd, TokenSubCmd, WalletSubCmd};
 
 #[tokio::main]
 async fn main() -> Result<(), FormattedMainError> {
     match try_main().await {
         Ok(x) => Ok(x),
         Err(err) => {
-            match &err {
-                MainError::CliParseError { source }=> {
-                    match source.kind() {
-                        clap::error::ErrorKind::DisplayHelp => {
-                            let _ = source.print();
-                            return Ok(())
-                        }
-                        _ => {},
-                    }
+            if let MainError::CliParseError { source } = &err {
+                if source.kind() == clap::error::ErrorKind::DisplayHelp {
+                    let _ = source.print();
+                    return Ok(())
                 }
-                _ => {},
             }
             Err(FormattedMainError{main_error: err})
         }
@@ -52,31 +52,81 @@ async fn try_main() -> MainResult<()> {
             clap_complete::generate(generator, &mut cmd, cmd_name, &mut std::io::stdout());
             return Ok(());
         }
+        SubCmd::Config { command: ConfigSubCmd::Init { wallet_count, ref cluster, force } } => {
+            let config_path = config::resolve_config_path(&cli.config_file).context(ConfigSnafu)?;
+            return cmd::init_config(&config_path, wallet_count, cluster, force)
+        }
+        SubCmd::Config { command: ConfigSubCmd::Validate { offline } } => {
+            let config_path = config::resolve_config_path(&cli.config_file).context(ConfigSnafu)?;
+            return cmd::validate_config(&config_path, offline).await
+        }
+        SubCmd::Config { command: ConfigSubCmd::Schema } => {
+            println!("{}", serde_json::to_string_pretty(&config::config_schema()).expect("schema is serializable"));
+            return Ok(())
+        }
         SubCmd::Wallet { ref command } => match command.clone() {
-            WalletSubCmd::Generate { count, save_to } => {
-                return cmd::generate_wallets(count, save_to).await
+            WalletSubCmd::Generate { count, save_to, save_args, mnemonic, words, passphrase, append_to_config } => {
+                return match (mnemonic, append_to_config) {
+                    (true, _) => cmd::generate_mnemonic_wallets(count, save_to, save_args.into(), words, passphrase.unwrap_or_default()).await,
+                    (false, true) => {
+                        ensure_config_wallets(&cli.overrides)?;
+                        let config_path = config::resolve_config_path(&cli.config_file).context(ConfigSnafu)?;
+                        cmd::generate_wallets_into_config(count, &config_path)
+                    }
+                    (false, false) => cmd::generate_wallets(count, save_to, save_args.into()).await,
+                }
+            }
+            WalletSubCmd::Recover { count, save_to, save_args, phrase, passphrase, start } => {
+                let seed = config::SeedConfig { phrase, passphrase: passphrase.unwrap_or_default() };
+                return cmd::recover_wallets(seed, start, count, save_to, save_args.into()).await
+            }
+            WalletSubCmd::Grind { prefix, suffix, ignore_case, count, save_to, save_args } => {
+                let pattern = wallet::VanityPattern::new(prefix, suffix, ignore_case).context(WalletSnafu)?;
+                return cmd::grind_wallets(pattern, count, save_to, save_args.into()).await
             }
             WalletSubCmd::Read { path } => {
                 println!("{}", wallet::convert_keypair_file_to_base58_string(path.as_path()).await.context(WalletSnafu)?);
                 return Ok(())
             }
+            WalletSubCmd::VerifyMessage { pubkey, signature, message } => {
+                return cmd::verify_message(pubkey, signature, message)
+            }
             _ => {}
         },
         _ => {}
     };
 
+    if let SubCmd::Wallet {
+        command: WalletSubCmd::Encrypt { .. } | WalletSubCmd::Decrypt | WalletSubCmd::Import { .. } | WalletSubCmd::Remove { .. }
+    } = cli.command {
+        ensure_config_wallets(&cli.overrides)?;
+    }
 
-    let cmd = cmd::CmdHandlers::new(config::Config::try_from_cli(&cli).await.context(ConfigSnafu)?);
+    let config_path = config::resolve_config_path(&cli.config_file).context(ConfigSnafu)?;
+    let cmd = cmd::CmdHandlers::new(config::Config::load(&config_path, cli.profile.as_deref(), &cli.overrides).await.context(ConfigSnafu)?);
 
-    match cli.command {
+    let result = match cli.command {
         SubCmd::Autocompletion { .. } => unreachable!("autocompletion subcommands should be already handled"),
         SubCmd::Wallet { command } => match command {
             WalletSubCmd::Generate { .. }
-            | WalletSubCmd::Read { .. }  => unreachable!("some wallet subcommands should be already handled"),
-            WalletSubCmd::List { pubkey, keypair } => cmd.print_wallets(pubkey, keypair),
-            WalletSubCmd::Save { target } => cmd.save_wallets_to(target.as_path()).await,
+            | WalletSubCmd::Recover { .. }
+            | WalletSubCmd::Grind { .. }
+            | WalletSubCmd::Read { .. }
+            | WalletSubCmd::VerifyMessage { .. } => unreachable!("some wallet subcommands should be already handled"),
+            WalletSubCmd::List { pubkey, keypair, source } => cmd.print_wallets(pubkey, keypair, source),
+            WalletSubCmd::Save { target, save_args } => cmd.save_wallets_to(target.as_path(), save_args.into()).await,
+            WalletSubCmd::SignMessage { signer, message } => cmd.sign_message(signer, message),
+            WalletSubCmd::Encrypt { keystore } => cmd.encrypt_wallets(&config_path, keystore),
+            WalletSubCmd::Decrypt => cmd.decrypt_wallets(&config_path),
+            WalletSubCmd::Import { path, link } => cmd.import_wallets(&config_path, &path, link),
+            WalletSubCmd::Remove { wallet } => cmd.remove_wallet(&config_path, wallet),
+        },
+        SubCmd::ShowConfig { reveal_secrets, format } => cmd.show_config(reveal_secrets, format),
+        SubCmd::Config { command } => match command {
+            ConfigSubCmd::Init { .. }
+            | ConfigSubCmd::Schema
+            | ConfigSubCmd::Validate { .. } => unreachable!("config subcommands should be already handled"),
         },
-        SubCmd::ShowConfig => cmd.show_config(),
         SubCmd::Balances => cmd.print_sol_balances().await,
         SubCmd::Airdrop { sols, confirm } => cmd.airdrop(sols, confirm).await,
         SubCmd::Token { command } => match command {
@@ -85,12 +135,26 @@ async fn try_main() -> MainResult<()> {
             TokenSubCmd::Balances => cmd.token_balances().await,
         },
         SubCmd::Test { command} => match command {
-            TestSubCmd::Transfer { command } => match command {
-                TestTransferSubCmd::Sols => cmd.test_batched_sols_transfer().await,
-                TestTransferSubCmd::Tokens => cmd.test_batched_tokens_transfer().await,
+            TestSubCmd::Transfer { run_id, send_via, concurrency, timeout, command } => {
+                let timeout = timeout.map(std::time::Duration::from_secs);
+                match command {
+                    TestTransferSubCmd::Sols => cmd.test_batched_sols_transfer(run_id, send_via, concurrency, timeout).await,
+                    TestTransferSubCmd::Tokens => cmd.test_batched_tokens_transfer(run_id, send_via, concurrency, timeout).await,
+                }
             }
         }
-    }
+    };
+    cmd.print_rpc_stats();
+    result
+}
+
+/// Commands rewriting the config file `wallets` work with the wallets of the file, not the overridden ones.
+fn ensure_config_wallets(overrides: &cli::ConfigOverrides) -> MainResult<()> {
+    snafu::ensure!(
+        overrides.wallets.is_empty() && overrides.keystore_path.is_none(),
+        CommandFailedSnafu { msg: "the command edits the config file wallets, unset --wallet/--keystore (TEST_TASK_WALLETS/TEST_TASK_KEYSTORE) overrides" }
+    );
+    Ok(())
 }
 
 pub(crate) type MainResult<T, E = MainError> = Result<T, E>;
@@ -107,11 +171,26 @@ pub(crate) enum MainError {
     #[snafu(display("Config error: {source}"))]
     ConfigError { source: config::ConfigError },
     #[snafu(display("RPC Error: {source}"))]
-    RpcError { source: solana_client::client_error::ClientError },
+    RpcError {
+        #[snafu(source(from(solana_client::client_error::ClientError, Box::new)))]
+        source: Box<solana_client::client_error::ClientError>,
+    },
     #[snafu(display("Wallet error: {source}"))]
     WalletError { source: wallet::WalletError },
     #[snafu(display("Token error: {source}"))]
-    TokenError { source: token::TokenError }
+    TokenError { source: token::TokenError },
+    #[snafu(display("Command failed: {msg}"))]
+    CommandFailed { msg: String },
+    #[snafu(display("Config file error: {source}"))]
+    ConfigFileError { source: config_file::ConfigFileError },
+    #[snafu(display("Keystore error: {source}"))]
+    KeystoreError { source: keystore::KeystoreError },
+    #[snafu(display("Confirmation error: {source}"))]
+    ConfirmError { source: confirm::ConfirmError },
+    #[snafu(display("Send error: {source}"))]
+    SendError { source: sender::SendError },
+    #[snafu(display("Task failed: {source}"))]
+    TaskJoinError { source: tokio::task::JoinError },
 }
 
 
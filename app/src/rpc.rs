@@ -0,0 +1,413 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    sync::{Arc, Weak, atomic::{AtomicBool, AtomicU64, Ordering}},
+    time::Duration,
+};
+use async_trait::async_trait;
+use rand::Rng as _;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    rpc_request::{RpcError, RpcRequest},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_rpc_client::http_sender::HttpSender;
+use solana_sdk::commitment_config::CommitmentConfig;
+use tokio::{sync::{Mutex, Semaphore}, time::Instant};
+use crate::config::{HealthCheckConfig, RetryConfig, RpcConfig};
+
+/// RPC client over [`ThrottledSender`] configured by `rpc` config section.
+pub(crate) fn connect(config: &RpcConfig, stats: Arc<RpcStats>) -> RpcClient {
+    RpcClient::new_sender(
+        ThrottledSender::new(config, stats),
+        RpcClientConfig::with_commitment(CommitmentConfig { commitment: config.commitment }),
+    )
+}
+
+/// [`RpcSender`] over HTTP with a request rate limit, a simultaneous requests cap
+/// and retries of failed requests with jittered exponential backoff.
+/// Requests are spread across healthy endpoints by weights, a failed request is retried on another endpoint.
+pub(crate) struct ThrottledSender {
+    endpoints: Arc<Vec<Endpoint>>,
+    /// failed endpoints are marked unhealthy only if the health check can bring them back
+    health_checks: bool,
+    rate_limit: Option<RateLimit>,
+    concurrency: Option<Semaphore>,
+    retry: RetryConfig,
+    stats: Arc<RpcStats>,
+}
+
+struct Endpoint {
+    http: HttpSender,
+    weight: u32,
+    healthy: AtomicBool,
+}
+
+/// Evenly spaced request slots.
+struct RateLimit {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+/// Transport counters shared by all the clients of a run.
+#[derive(Debug, Default)]
+pub(crate) struct RpcStats {
+    requests: AtomicU64,
+    retries: AtomicU64,
+    failures: AtomicU64,
+    /// HTTP 429 responses
+    server_throttled: AtomicU64,
+    rate_limit_wait_us: AtomicU64,
+    concurrency_wait_us: AtomicU64,
+    backoff_us: AtomicU64,
+    /// retries on another endpoint
+    failovers: AtomicU64,
+    /// requests by endpoint URL
+    endpoint_requests: std::sync::Mutex<BTreeMap<String, u64>>,
+}
+
+impl ThrottledSender {
+    pub(crate) fn new(config: &RpcConfig, stats: Arc<RpcStats>) -> Self {
+        let endpoints = Arc::new(
+            config.all_endpoints().into_iter()
+                .map(|endpoint| Endpoint {
+                    http: HttpSender::new(endpoint.uri.0.to_string()),
+                    weight: endpoint.weight,
+                    healthy: AtomicBool::new(true),
+                })
+                .collect::<Vec<_>>()
+        );
+        let health_checks = endpoints.len() > 1 && config.health_check.interval_secs > 0;
+        if health_checks {
+            tokio::spawn(check_health(Arc::downgrade(&endpoints), config.health_check.clone()));
+        }
+        Self {
+            endpoints,
+            health_checks,
+            rate_limit: config.rate_limit_per_sec.filter(|rate| *rate > 0).map(|rate| RateLimit {
+                interval: Duration::from_secs(1) / rate,
+                next_slot: Mutex::new(Instant::now()),
+            }),
+            concurrency: config.max_concurrent_requests.filter(|max| *max > 0).map(Semaphore::new),
+            retry: config.retry.clone(),
+            stats,
+        }
+    }
+
+    /// Weighted random choice among healthy endpoints (among all of them if none is healthy),
+    /// `exclude` is avoided if there is another choice.
+    fn pick_endpoint(&self, exclude: Option<usize>) -> usize {
+        let healthy = (0..self.endpoints.len())
+            .filter(|i| self.endpoints[*i].healthy.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let mut candidates = match healthy.is_empty() {
+            true => (0..self.endpoints.len()).collect(),
+            false => healthy,
+        };
+        if candidates.len() > 1 {
+            candidates.retain(|i| Some(*i) != exclude);
+        }
+        let total_weight = candidates.iter().map(|i| self.endpoints[*i].weight as u64).sum::<u64>();
+        if total_weight == 0 {
+            return candidates[rand::thread_rng().gen_range(0..candidates.len())]
+        }
+        let mut point = rand::thread_rng().gen_range(0..total_weight);
+        for i in candidates.iter() {
+            let weight = self.endpoints[*i].weight as u64;
+            if point < weight { return *i }
+            point -= weight;
+        }
+        unreachable!("the point is less than the total weight")
+    }
+
+    async fn send_once(&self, endpoint: &Endpoint, request: RpcRequest, params: serde_json::Value) -> ClientResult<serde_json::Value> {
+        let started = Instant::now();
+        let _permit = match &self.concurrency {
+            Some(semaphore) => Some(semaphore.acquire().await.expect("the semaphore is never closed")),
+            None => None,
+        };
+        self.stats.add_time(&self.stats.concurrency_wait_us, started.elapsed());
+        if let Some(rate_limit) = &self.rate_limit {
+            self.stats.add_time(&self.stats.rate_limit_wait_us, rate_limit.acquire().await);
+        }
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+        *self.stats.endpoint_requests.lock().expect("not poisoned").entry(endpoint.http.url()).or_default() += 1;
+        endpoint.http.send(request, params).await
+    }
+
+    /// `initial * 2^attempt` capped by the max backoff, randomized within its upper half.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let max = self.retry.initial_backoff_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.retry.max_backoff_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(max / 2..=max))
+    }
+}
+
+#[async_trait]
+impl RpcSender for ThrottledSender {
+    async fn send(&self, request: RpcRequest, params: serde_json::Value) -> ClientResult<serde_json::Value> {
+        let mut attempt = 0;
+        // reads are spread across the endpoints, transactions are sent to the first healthy one
+        let mut endpoint_index = match request {
+            RpcRequest::SendTransaction => (0..self.endpoints.len())
+                .find(|i| self.endpoints[*i].healthy.load(Ordering::Relaxed))
+                .unwrap_or_default(),
+            _ => self.pick_endpoint(None),
+        };
+        loop {
+            let endpoint = &self.endpoints[endpoint_index];
+            let error = match self.send_once(endpoint, request, params.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+            let throttled = is_status(&error, 429);
+            if throttled {
+                self.stats.server_throttled.fetch_add(1, Ordering::Relaxed);
+            }
+            if attempt >= self.retry.max_retries || !is_retryable(&error) {
+                self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(error)
+            }
+            attempt += 1;
+            self.stats.retries.fetch_add(1, Ordering::Relaxed);
+            // a throttling endpoint is working, it's waited for instead of failing over
+            if self.endpoints.len() > 1 && !throttled {
+                if self.health_checks {
+                    // the health check brings it back when it recovers
+                    endpoint.healthy.store(false, Ordering::Relaxed);
+                }
+                let next_index = self.pick_endpoint(Some(endpoint_index));
+                if next_index != endpoint_index && self.endpoints[next_index].healthy.load(Ordering::Relaxed) {
+                    self.stats.failovers.fetch_add(1, Ordering::Relaxed);
+                    endpoint_index = next_index;
+                    continue
+                }
+                endpoint_index = next_index;
+            }
+            let backoff = self.backoff(attempt - 1);
+            self.stats.add_time(&self.stats.backoff_us, backoff);
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.endpoints.iter()
+            .map(|endpoint| endpoint.http.get_transport_stats())
+            .fold(RpcTransportStats::default(), |sum, stats| RpcTransportStats {
+                request_count: sum.request_count + stats.request_count,
+                elapsed_time: sum.elapsed_time + stats.elapsed_time,
+                rate_limited_time: sum.rate_limited_time + stats.rate_limited_time,
+            })
+    }
+
+    /// URL of the primary endpoint (`rpc.uri`).
+    fn url(&self) -> String {
+        self.endpoints[0].http.url()
+    }
+}
+
+/// Marks endpoints healthy if `getHealth` succeeds and their slot isn't behind the most advanced one
+/// more than the allowed lag. Stops when the sender is dropped.
+async fn check_health(endpoints: Weak<Vec<Endpoint>>, config: HealthCheckConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(endpoints) = endpoints.upgrade() else { return };
+        let slots = futures::future::join_all(endpoints.iter().map(|endpoint| async {
+            endpoint.http.send(RpcRequest::GetHealth, serde_json::Value::Null).await.ok()?;
+            let slot = endpoint.http.send(RpcRequest::GetSlot, serde_json::json!([{ "commitment": "processed" }])).await.ok()?;
+            slot.as_u64()
+        })).await;
+        let max_slot = slots.iter().flatten().max().copied().unwrap_or_default();
+        for (endpoint, slot) in endpoints.iter().zip(slots) {
+            let healthy = slot.is_some_and(|slot| max_slot - slot <= config.max_slot_lag);
+            endpoint.healthy.store(healthy, Ordering::Relaxed);
+        }
+    }
+}
+
+impl RateLimit {
+    /// Waits for the next free slot, returns the waiting time.
+    async fn acquire(&self) -> Duration {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        let wait = slot.saturating_duration_since(Instant::now());
+        tokio::time::sleep_until(slot).await;
+        wait
+    }
+}
+
+/// Connection errors, timeouts, HTTP 429 and 5xx, unhealthy node responses.
+fn is_retryable(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Io(_) => true,
+        ClientErrorKind::Reqwest(e) => e.is_connect() || e.is_timeout()
+            || e.status().is_some_and(|status| status.as_u16() == 429 || status.is_server_error()),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => *code == JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+        _ => false,
+    }
+}
+
+fn is_status(error: &ClientError, status: u16) -> bool {
+    matches!(error.kind(), ClientErrorKind::Reqwest(e) if e.status().is_some_and(|s| s.as_u16() == status))
+}
+
+impl RpcStats {
+    fn add_time(&self, counter: &AtomicU64, duration: Duration) {
+        counter.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+}
+
+impl Display for RpcStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let time = |counter: &AtomicU64| Duration::from_micros(counter.load(Ordering::Relaxed));
+        write!(
+            f,
+            "RPC: {} requests, {} retries, {} failed, {} throttled by the server (HTTP 429); \
+            total waits: {:?} for the rate limit, {:?} for a free request slot, {:?} in backoff",
+            self.requests(),
+            self.retries.load(Ordering::Relaxed),
+            self.failures.load(Ordering::Relaxed),
+            self.server_throttled.load(Ordering::Relaxed),
+            time(&self.rate_limit_wait_us),
+            time(&self.concurrency_wait_us),
+            time(&self.backoff_us),
+        )?;
+        let failovers = self.failovers.load(Ordering::Relaxed);
+        if failovers > 0 {
+            write!(f, "; {failovers} failovers")?;
+        }
+        let endpoint_requests = self.endpoint_requests.lock().expect("not poisoned");
+        if endpoint_requests.len() > 1 {
+            for (url, requests) in endpoint_requests.iter() {
+                write!(f, "\n  {url}: {requests} requests")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_request::RpcResponseErrorData;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    fn rpc_config(yaml: &str) -> RpcConfig {
+        serde_yaml::from_str(yaml).expect("valid rpc config")
+    }
+
+    /// HTTP server answering every request with the status (and `Retry-After: 0` for 429), returns its URL.
+    async fn http_server(status: u16) -> String {
+        http_server_with(status, "").await
+    }
+
+    /// HTTP server answering every request with the status and the body, returns its URL.
+    async fn http_server_with(status: u16, body: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 {status} Status\r\nretry-after: 0\r\ncontent-type: application/json\r\n\
+                    content-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len(),
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    async fn http_error(url: &str) -> ClientError {
+        HttpSender::new(url).send(RpcRequest::GetHealth, serde_json::Value::Null).await
+            .expect_err("the request fails")
+    }
+
+    fn rpc_response_error(code: i64) -> ClientError {
+        RpcError::RpcResponseError { code, message: "error".into(), data: RpcResponseErrorData::Empty }.into()
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let config = rpc_config("{ uri: http://localhost:8899, retry: { initial_backoff_ms: 100, max_backoff_ms: 1000 } }");
+        let sender = ThrottledSender::new(&config, Arc::default());
+        for (attempt, max) in [(0, 100), (1, 200), (3, 800), (4, 1000), (40, 1000), (u32::MAX, 1000)] {
+            for _ in 0..100 {
+                let backoff = sender.backoff(attempt).as_millis() as u64;
+                assert!((max / 2..=max).contains(&backoff), "attempt {attempt}: {backoff}ms");
+            }
+        }
+    }
+
+    #[test]
+    fn rpc_errors_retryability() {
+        let io_error = ClientError::from(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset"));
+        assert!(is_retryable(&io_error));
+        assert!(is_retryable(&rpc_response_error(JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY)));
+        assert!(!is_retryable(&rpc_response_error(-32602)));
+        assert!(!is_retryable(&RpcError::ForUser("bad request".into()).into()));
+        assert!(!is_retryable(&ClientErrorKind::Custom("custom".into()).into()));
+    }
+
+    #[tokio::test]
+    async fn http_errors_retryability() {
+        // nothing listens on the port of a dropped listener
+        let closed_port = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        assert!(is_retryable(&http_error(&format!("http://127.0.0.1:{closed_port}")).await));
+        for (status, retryable) in [(429, true), (500, true), (503, true), (400, false), (404, false)] {
+            let error = http_error(&http_server(status).await).await;
+            assert_eq!(is_retryable(&error), retryable, "HTTP {status}");
+            assert_eq!(is_status(&error, 429), status == 429);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_are_limited() {
+        let url = http_server(503).await;
+        let config = rpc_config(&format!("{{ uri: \"{url}\", retry: {{ max_retries: 2, initial_backoff_ms: 1, max_backoff_ms: 1 }} }}"));
+        let stats = Arc::new(RpcStats::default());
+        let sender = ThrottledSender::new(&config, stats.clone());
+        assert!(sender.send(RpcRequest::GetHealth, serde_json::Value::Null).await.is_err());
+        assert_eq!(stats.requests(), 3);
+        assert_eq!(stats.retries.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.failures.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_endpoint_stays_healthy_without_health_checks() {
+        let failing = http_server(503).await;
+        let working = http_server_with(200, r#"{"jsonrpc":"2.0","result":"ok","id":1}"#).await;
+        let config = rpc_config(&format!(
+            "{{ uri: \"{failing}\", endpoints: [{{ uri: \"{working}\" }}], health_check: {{ interval_secs: 0 }}, \
+            retry: {{ max_retries: 1, initial_backoff_ms: 1, max_backoff_ms: 1 }} }}"
+        ));
+        let stats = Arc::new(RpcStats::default());
+        let sender = ThrottledSender::new(&config, stats.clone());
+        assert!(!sender.health_checks);
+        // transactions go to the first healthy endpoint, the failing one is tried first every time
+        for _ in 0..3 {
+            sender.send(RpcRequest::SendTransaction, serde_json::Value::Null).await.unwrap();
+        }
+        // each request is retried on the other endpoint, the failed one isn't excluded for good
+        assert!(sender.endpoints.iter().all(|endpoint| endpoint.healthy.load(Ordering::Relaxed)));
+        assert_eq!(stats.requests(), 6);
+        assert_eq!(stats.failovers.load(Ordering::Relaxed), 3);
+        assert_eq!(stats.failures.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,206 @@
+use std::{sync::Arc, time::Duration};
+use snafu::{OptionExt as _, ResultExt as _, Snafu};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::{rpc_client::RpcClient, tpu_client::{TpuClient, TpuSenderError}},
+    rpc_config::RpcSendTransactionConfig,
+    tpu_client::TpuClientConfig,
+};
+use solana_quic_client::{QuicConfig, QuicConnectionManager, QuicPool};
+use solana_sdk::{
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    hash::Hash,
+    signature::Signature,
+    transaction::Transaction,
+    transport::TransportError,
+};
+use tokio::time::sleep;
+use crate::{blockhash::BlockhashCache, confirm::{ConfirmError, Confirmer}, config::RpcConfig};
+
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(2);
+
+pub(crate) type QuicTpuClient = TpuClient<QuicPool, QuicConnectionManager, QuicConfig>;
+
+/// Where transactions are sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Route {
+    /// `sendTransaction` RPC request
+    Rpc,
+    /// TPU ports of the current and upcoming leaders over QUIC
+    Tpu,
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Route::Rpc => write!(f, "rpc"),
+            Route::Tpu => write!(f, "tpu"),
+        }
+    }
+}
+
+/// TPU client which learns the leader schedule and slot updates by the `rpc` endpoints.
+pub(crate) async fn connect_tpu(rpc_client: Arc<RpcClient>, config: &RpcConfig) -> SendResult<QuicTpuClient> {
+    QuicTpuClient::new(env!("CARGO_PKG_NAME"), rpc_client, config.ws_url().as_str(), TpuClientConfig::default())
+        .await.context(TpuConnectFailedSnafu)
+}
+
+/// Sends transactions until they reach a commitment level: a transaction is rebroadcast
+/// while its blockhash is valid, then it's rebuilt with a fresh blockhash and signed again.
+pub(crate) struct TransactionSender {
+    rpc_client: Arc<RpcClient>,
+    blockhashes: Arc<BlockhashCache>,
+    confirmer: Arc<Confirmer>,
+    max_attempts: u32,
+    tpu: Option<QuicTpuClient>,
+}
+
+impl TransactionSender {
+    pub(crate) fn new(
+        rpc_client: Arc<RpcClient>,
+        blockhashes: Arc<BlockhashCache>,
+        confirmer: Arc<Confirmer>,
+        max_attempts: u32,
+    ) -> Self {
+        Self { rpc_client, blockhashes, confirmer, max_attempts, tpu: None }
+    }
+
+    /// Enables [`Route::Tpu`].
+    pub(crate) fn with_tpu(mut self, tpu: QuicTpuClient) -> Self {
+        self.tpu = Some(tpu);
+        self
+    }
+
+    /// Returns the signature of the attempt which reached `commitment`.
+    /// `build` makes a signed transaction with the given blockhash for every attempt,
+    /// `on_attempt` gets the attempt number (starting with 1) and its signature.
+    pub(crate) async fn send_with_retry(
+        &self,
+        build: impl Fn(Hash) -> Transaction,
+        route: Route,
+        commitment: CommitmentLevel,
+        mut on_attempt: impl FnMut(u32, &Signature),
+    ) -> SendResult<Signature> {
+        let mut expired = None;
+        for attempt in 1..=self.max_attempts {
+            let blockhash = match &expired {
+                None => self.blockhashes.get().await,
+                Some(expired) => self.blockhashes.get_newer_than(expired).await,
+            }.context(BlockhashRequestFailedSnafu)?;
+            let tx = build(blockhash.hash);
+            let signature = tx.signatures[0];
+            on_attempt(attempt, &signature);
+            self.broadcast(&tx, route, false).await?;
+            let mut confirmed = Box::pin(self.confirmer.confirm(&signature, commitment));
+            loop {
+                tokio::select! {
+                    result = &mut confirmed => match result {
+                        Ok(()) => return Ok(signature),
+                        // expiration is checked below
+                        Err(ConfirmError::ConfirmationTimeout { .. }) => {
+                            confirmed = Box::pin(self.confirmer.confirm(&signature, commitment));
+                        }
+                        Err(e) => return Err(e).context(ConfirmationFailedSnafu),
+                    },
+                    _ = sleep(REBROADCAST_INTERVAL) => {}
+                }
+                if self.blockhashes.is_expired(&blockhash).await.context(BlockHeightRequestFailedSnafu)? {
+                    break
+                }
+                self.broadcast(&tx, route, true).await?;
+            }
+            // it could have got into a block right before the expiration, it's signed again only if it hasn't
+            let status = self.rpc_client
+                .get_signature_status_with_commitment(&signature, CommitmentConfig::processed()).await
+                .context(StatusRequestFailedSnafu)?;
+            if status.is_some() {
+                confirmed.await.context(ConfirmationFailedSnafu)?;
+                return Ok(signature)
+            }
+            expired = Some(blockhash);
+        }
+        BlockhashExpiredSnafu { attempts: self.max_attempts }.fail()
+    }
+
+    /// The first sending and rebroadcasts alike: a rejected transaction fails, a transport error is left
+    /// to the next rebroadcast (the RPC client has already retried the request).
+    async fn broadcast(&self, tx: &Transaction, route: Route, rebroadcast: bool) -> SendResult<()> {
+        match self.send(tx, route, rebroadcast).await {
+            Err(e) if !e.is_transport_error() => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn send(&self, tx: &Transaction, route: Route, rebroadcast: bool) -> SendResult<()> {
+        match route {
+            Route::Rpc => {
+                let config = RpcSendTransactionConfig {
+                    // already processed transactions are rejected by preflight checks
+                    skip_preflight: rebroadcast,
+                    preflight_commitment: Some(self.rpc_client.commitment().commitment),
+                    ..Default::default()
+                };
+                self.rpc_client.send_transaction_with_config(tx, config).await.context(SendFailedSnafu)?;
+            }
+            Route::Tpu => {
+                let tpu = self.tpu.as_ref().context(TpuNotConnectedSnafu)?;
+                tpu.try_send_transaction(tx).await.context(TpuSendFailedSnafu)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) type SendResult<T, E = SendError> = Result<T, E>;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub(crate) enum SendError {
+    #[snafu(display("can't get a blockhash: {source}"))]
+    BlockhashRequestFailed {
+        #[snafu(source(from(ClientError, Box::new)))]
+        source: Box<ClientError>,
+    },
+    #[snafu(display("can't get the block height: {source}"))]
+    BlockHeightRequestFailed {
+        #[snafu(source(from(ClientError, Box::new)))]
+        source: Box<ClientError>,
+    },
+    #[snafu(display("can't check if the expired transaction has landed, it isn't signed again: {source}"))]
+    StatusRequestFailed {
+        #[snafu(source(from(ClientError, Box::new)))]
+        source: Box<ClientError>,
+    },
+    #[snafu(display("sending failed: {source}"))]
+    SendFailed {
+        #[snafu(source(from(ClientError, Box::new)))]
+        source: Box<ClientError>,
+    },
+    #[snafu(display("TPU connection failed: {source}"))]
+    TpuConnectFailed {
+        #[snafu(source(from(TpuSenderError, Box::new)))]
+        source: Box<TpuSenderError>,
+    },
+    #[snafu(display("TPU client isn't connected"))]
+    TpuNotConnected,
+    #[snafu(display("sending to TPU failed: {source}"))]
+    TpuSendFailed {
+        #[snafu(source(from(TransportError, Box::new)))]
+        source: Box<TransportError>,
+    },
+    #[snafu(display("{source}"))]
+    ConfirmationFailed { source: ConfirmError },
+    #[snafu(display("the transaction isn't confirmed in {attempts} attempt(s), the blockhash of the last one has expired"))]
+    BlockhashExpired { attempts: u32 },
+}
+
+impl SendError {
+    /// The transaction might not have reached the node, unlike a rejected one.
+    fn is_transport_error(&self) -> bool {
+        match self {
+            SendError::SendFailed { source } => matches!(source.kind(), ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_)),
+            SendError::TpuSendFailed { source } => !matches!(source.as_ref(), TransportError::TransactionError(_)),
+            _ => false,
+        }
+    }
+}
@@ -5,9 +5,12 @@ use snafu::{ResultExt, Snafu};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_request::TokenAccountsFilter;
 use solana_sdk::{
+    hash::Hash,
+    instruction::Instruction,
     pubkey::{Pubkey, ParsePubkeyError},
     signature::Signature,
     signer::Signer,
+    transaction::Transaction,
 };
 use spl_token_client::{
     client::{
@@ -18,17 +21,24 @@ use spl_token_client::{
     token::{Token as SplToken, TokenError as SplTokenError},
 };
 use tokio::sync::Mutex;
+use crate::{blockhash::BlockhashCache, wallet::WithMemo as _};
 
 #[derive(Clone)]
 pub(crate) struct Token {
     pub(crate) rpc_client: Arc<RpcClient>,
     pub(crate) mint: Pubkey,
     pub(crate) owner: Arc<dyn Signer>,
-    pub(crate) spl_token: Arc<SplToken<ProgramRpcClientSendTransaction>>
+    pub(crate) spl_token: Arc<SplToken<ProgramRpcClientSendTransaction>>,
+    blockhashes: Arc<BlockhashCache>,
 }
 
-pub(crate) async fn deploy(rpc_client: Arc<RpcClient>, mint: Arc<dyn Signer>, owner: Arc<dyn Signer>) -> TokenResult<(Signature, Token)> {
-    let token = Token::new(rpc_client, mint.pubkey().clone(), owner.clone());
+pub(crate) async fn deploy(
+    rpc_client: Arc<RpcClient>,
+    blockhashes: Arc<BlockhashCache>,
+    mint: Arc<dyn Signer>,
+    owner: Arc<dyn Signer>,
+) -> TokenResult<(Signature, Token)> {
+    let token = Token::new(rpc_client, blockhashes, mint.pubkey(), owner.clone());
     let token_owner_pubkey = &owner.pubkey();
     let rpc_client_response = token.spl_token.create_mint(
         token_owner_pubkey,
@@ -42,12 +52,15 @@ pub(crate) async fn deploy(rpc_client: Arc<RpcClient>, mint: Arc<dyn Signer>, ow
 impl Token {
     pub(crate) const DECIMALS: u8 = 6;
 
-    pub(crate) fn new(rpc_client: Arc<RpcClient>, mint: Pubkey, owner: Arc<dyn Signer>) -> Self {
+    // spl-token-client requires Arc<dyn Signer> which is not Send + Sync
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub(crate) fn new(rpc_client: Arc<RpcClient>, blockhashes: Arc<BlockhashCache>, mint: Pubkey, owner: Arc<dyn Signer>) -> Self {
         let token_client = Arc::new(ProgramRpcClient::new(
             rpc_client.clone(), ProgramRpcClientSendTransaction
         ));
         let token_program = spl_token::id();
         Token {
+            blockhashes,
             rpc_client,
             mint,
             owner: Arc::clone(&owner),
@@ -80,7 +93,7 @@ impl Token {
     }
 
     pub(crate) async fn get_token_account_balance(&self, token_account: &Pubkey) -> TokenResult<u64> {
-        let sender_ui_balance = self.rpc_client.get_token_account_balance(&token_account)
+        let sender_ui_balance = self.rpc_client.get_token_account_balance(token_account)
             .await.context(TokenRpcSnafu)?;
         Ok(Self::coins_to_subunits(
             sender_ui_balance.ui_amount
@@ -98,7 +111,7 @@ impl Token {
 
     pub(crate) async fn get_accumulated_balance(&self, holder: &Pubkey) -> TokenResult<AccumulatedTokenBalance> {
         let token_accounts = self.rpc_client.get_token_accounts_by_owner(
-            holder, TokenAccountsFilter::Mint(self.mint.clone()),
+            holder, TokenAccountsFilter::Mint(self.mint),
         ).await.context(TokenRpcSnafu)?;
         let token_accounts = token_accounts.into_iter()
             .map(|x| Pubkey::from_str(&x.pubkey))
@@ -131,19 +144,16 @@ impl Token {
         source_ta: &Pubkey,
         destination_ta: &Pubkey,
         subunits: u64,
+        memo: Option<impl AsRef<str>>,
     ) -> TokenResult<Signature> {
-        let sender_pk = sender.pubkey();
         // TODO: check source token account belongs to sender
         if self.get_token_account_balance(source_ta).await? < subunits {
             return Err(TokenError::InsufficientBalance);
         }
-        Ok(res_tx(self.spl_token.transfer(
-            &source_ta,
-            &destination_ta,
-            &sender_pk,
-            subunits,
-            &[sender],
-        ).await.context(SplTokenSnafu)?))
+        let instructions = self.transfer_checked_instructions(&sender.pubkey(), source_ta, destination_ta, subunits, memo)?;
+        let recent_blockhash = self.blockhashes.get().await.context(TokenRpcSnafu)?.hash;
+        let tx = self.signed_tx(&instructions, sender, recent_blockhash);
+        self.rpc_client.send_transaction(&tx).await.context(TokenRpcSnafu)
     }
 
     pub(crate) async fn transfer(
@@ -151,14 +161,65 @@ impl Token {
         sender: &(dyn Signer + Sync),
         receiver: &Pubkey,
         subunits: u64,
+        memo: Option<impl AsRef<str>>,
     ) -> TokenResult<Signature> {
         self.transfer_between_token_accounts(
             sender,
             &self.spl_token.get_associated_token_address(&sender.pubkey()),
             &self.spl_token.get_associated_token_address(receiver),
-            subunits
+            subunits,
+            memo,
         ).await
     }
+
+    /// Instructions of a transfer between associated token accounts.
+    pub(crate) fn transfer_instructions(
+        &self,
+        sender: &Pubkey,
+        receiver: &Pubkey,
+        subunits: u64,
+        memo: Option<impl AsRef<str>>,
+    ) -> TokenResult<Vec<Instruction>> {
+        self.transfer_checked_instructions(
+            sender,
+            &self.spl_token.get_associated_token_address(sender),
+            &self.spl_token.get_associated_token_address(receiver),
+            subunits,
+            memo,
+        )
+    }
+
+    /// Transaction of the instructions paid by the token owner.
+    pub(crate) fn signed_tx(&self, instructions: &[Instruction], signer: &(dyn Signer + Sync), recent_blockhash: Hash) -> Transaction {
+        Transaction::new_signed_with_payer(
+            instructions, Some(&self.owner.pubkey()), &[self.owner.as_ref(), signer], recent_blockhash
+        )
+    }
+
+    fn transfer_checked_instructions(
+        &self,
+        sender: &Pubkey,
+        source_ta: &Pubkey,
+        destination_ta: &Pubkey,
+        subunits: u64,
+        memo: Option<impl AsRef<str>>,
+    ) -> TokenResult<Vec<Instruction>> {
+        // SplToken::with_memo keeps the memo in the shared client state,
+        // so concurrent transfers would race for it. Building the transaction here instead,
+        // which also lets it use the shared blockhash.
+        Ok(vec![
+            spl_token::instruction::transfer_checked(
+                &spl_token::id(),
+                source_ta,
+                &self.mint,
+                destination_ta,
+                sender,
+                &[],
+                subunits,
+                Self::DECIMALS,
+            ).context(ProgramSnafu)?,
+        ].with_memo(memo))
+    }
 }
 
 pub(crate) struct AccumulatedTokenBalance {
@@ -177,15 +238,24 @@ pub(crate) type TokenResult<T> = Result<T, TokenError>;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub))]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum TokenError {
     #[snafu(display("Unexpected token error: {msg}"))]
     Unexpected { msg: String },
     #[snafu(display("SPL token error: {source}"))]
-    SplTokenError { source: SplTokenError },
+    SplTokenError {
+        #[snafu(source(from(SplTokenError, Box::new)))]
+        source: Box<SplTokenError>,
+    },
     #[snafu(display("RPC error: {source}"))]
-    TokenRpcError { source: solana_client::client_error::ClientError },
+    TokenRpcError {
+        #[snafu(source(from(solana_client::client_error::ClientError, Box::new)))]
+        source: Box<solana_client::client_error::ClientError>,
+    },
     #[snafu(display("Insufficient token balance"))]
     InsufficientBalance,
     #[snafu(display("{source}"))]
     ParsePubkeyError { source: ParsePubkeyError },
+    #[snafu(display("Program error: {source}"))]
+    ProgramError { source: solana_sdk::program_error::ProgramError },
 }
@@ -1,19 +1,50 @@
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, atomic::{AtomicBool, AtomicU64, Ordering}};
 use snafu::{ResultExt, Snafu};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
     bs58,
+    hash::Hash,
     instruction::Instruction,
+    offchain_message::OffchainMessage,
     pubkey::Pubkey,
-    signature::Signature,
+    signature::{Keypair, Signature},
     signer::Signer,
     system_instruction,
     transaction::Transaction,
 };
-use crate::config::{KeypairList, KeypairSerde};
+use crate::{blockhash::BlockhashCache, config::{KeypairList, KeypairSerde, PubkeySerde}};
 
-pub(crate) async fn save_wallets_to(wallets: KeypairList, save_to: &Path) -> WalletResult<()> {
+/// Keypair file naming for [`save_wallets_to`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum FileNaming {
+    /// `id000000.json`, `id000001.json`, ...
+    #[default]
+    Index,
+    /// `<pubkey>.json`
+    Pubkey,
+    /// `<wallet name>.json` (falls back to the index naming for unnamed wallets)
+    Name,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SaveOptions {
+    /// overwrite existing files
+    pub(crate) force: bool,
+    pub(crate) naming: FileNaming,
+    /// print public keys instead of secret keys
+    pub(crate) no_secrets: bool,
+}
+
+/// Saves wallets as solana-cli compatible json files (`0600` permissions, written atomically via a temp file).
+/// `names` are used by [`FileNaming::Name`], it may be shorter than `wallets`.
+pub(crate) async fn save_wallets_to(
+    wallets: KeypairList,
+    names: &[Option<String>],
+    save_to: &Path,
+    options: &SaveOptions,
+) -> WalletResult<()> {
     let save_path_to_str = save_to.to_string_lossy();
     if !std::fs::metadata(save_to)
         .context(SaveJsonWalletToFileSnafu { path: save_path_to_str.to_string() })?
@@ -23,22 +54,85 @@ pub(crate) async fn save_wallets_to(wallets: KeypairList, save_to: &Path) -> Wal
             path: save_path_to_str.to_string()
         });
     }
-    for (i, KeypairSerde(kp)) in wallets.0.iter().enumerate() {
+    let files = wallets.0.iter().enumerate()
+        .map(|(i, kp)| {
+            let name = names.get(i).cloned().flatten();
+            save_to.join(wallet_file_name(i, kp, name.as_deref(), options.naming))
+        })
+        .collect::<Vec<_>>();
+    for (i, file) in files.iter().enumerate() {
+        if let Some(first) = files[..i].iter().position(|other| other == file) {
+            return Err(WalletError::DuplicateWalletFile { path: file.to_string_lossy().to_string(), first, second: i });
+        }
+    }
+    for file in files.iter() {
+        if !options.force && file.exists() {
+            return Err(WalletError::WalletFileExists { path: file.to_string_lossy().to_string() });
+        }
+    }
+    for (KeypairSerde(kp), wallet_file_path_buf) in wallets.0.iter().zip(files) {
         let kp_bytes = kp.to_bytes();
         let wallet_json = serde_json::to_string(kp_bytes.as_slice())
             .context(SerializeWalletIntoJsonSnafu)?;
-        let wallet_file_path_buf = save_to.join(format!("id{i:06}.json"));
         let wallet_file_path_string = wallet_file_path_buf.to_string_lossy().to_string();
-        let save_error_ctx = SaveJsonWalletToFileSnafu { path: wallet_file_path_string.clone() };
-        let mut wallet_file = std::fs::File::create(wallet_file_path_buf)
-            .context(save_error_ctx.clone())?;
-        wallet_file.write_all(wallet_json.as_bytes()).context(save_error_ctx)?;
-        let kp_base58_encoded = bs58::encode(kp_bytes).into_string();
-        println!("- keypair: {kp_base58_encoded}\n  saved_to: {wallet_file_path_string}");
+        write_secret_file_atomically(&wallet_file_path_buf, wallet_json.as_bytes())
+            .context(SaveJsonWalletToFileSnafu { path: wallet_file_path_string.clone() })?;
+        match options.no_secrets {
+            true => println!("- pubkey: {}\n  saved_to: {wallet_file_path_string}", kp.pubkey()),
+            false => {
+                let kp_base58_encoded = bs58::encode(kp_bytes).into_string();
+                println!("- keypair: {kp_base58_encoded}\n  saved_to: {wallet_file_path_string}")
+            }
+        }
     }
     Ok(())
 }
 
+fn wallet_file_name(index: usize, kp: &KeypairSerde, name: Option<&str>, naming: FileNaming) -> String {
+    let sanitized_name = name.map(|name| name.chars()
+        .map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect::<String>());
+    match (naming, sanitized_name) {
+        (FileNaming::Pubkey, _) => format!("{}.json", kp.pubkey()),
+        (FileNaming::Name, Some(name)) => format!("{name}.json"),
+        (FileNaming::Name, None) | (FileNaming::Index, _) => format!("id{index:06}.json"),
+    }
+}
+
+/// Writes a temp file readable only by the owner next to the target and renames it to the target.
+pub(crate) fn write_secret_file_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    write_file_atomically(path, content, None)
+}
+
+/// Writes a temp file next to the target and renames it to the target. The temp file is readable only by the owner
+/// until `permissions` are set on it (before the renaming).
+pub(crate) fn write_file_atomically(path: &Path, content: &[u8], permissions: Option<std::fs::Permissions>) -> std::io::Result<()> {
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut open_options = std::fs::OpenOptions::new();
+    open_options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt as _;
+        open_options.mode(0o600);
+    }
+    let result = open_options.open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            if let Some(permissions) = permissions {
+                file.set_permissions(permissions)?;
+            }
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
 pub(crate) async fn convert_keypair_file_to_base58_string(wallet_path: &Path) -> WalletResult<String> {
     let wallet_file = std::fs::File::open(wallet_path)
         .context(ReadJsonWalletFileSnafu { path: wallet_path.to_string_lossy() })?;
@@ -47,30 +141,180 @@ pub(crate) async fn convert_keypair_file_to_base58_string(wallet_path: &Path) ->
     Ok(bs58::encode(kp_bytes).into_string())
 }
 
+pub(crate) fn read_keypair_file(wallet_path: &Path) -> WalletResult<KeypairSerde> {
+    let wallet_file = std::fs::File::open(wallet_path)
+        .context(ReadJsonWalletFileSnafu { path: wallet_path.to_string_lossy() })?;
+    let kp_bytes: Vec<u8> = serde_json::from_reader(wallet_file)
+        .context(ParseJsonWalletFileSnafu { path: wallet_path.to_string_lossy() })?;
+    KeypairSerde::from_bytes(&kp_bytes)
+        .map_err(|msg| WalletError::InvalidKeypairFile { path: wallet_path.to_string_lossy().to_string(), msg })
+}
+
+/// Keypair json files matching the path: a directory (all `*.json` files in it)
+/// or a file name pattern with `*` and `?` wildcards, e.g. `workdir/wallets/p1w*.json`.
+/// Files are sorted by name.
+pub(crate) fn find_keypair_files(path: &Path) -> WalletResult<Vec<PathBuf>> {
+    let (dir, pattern) = match path.is_dir() {
+        true => (path.to_path_buf(), "*.json".to_string()),
+        false => (
+            path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf(),
+            path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        ),
+    };
+    let entries = std::fs::read_dir(&dir)
+        .context(ReadJsonWalletFileSnafu { path: dir.to_string_lossy() })?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.context(ReadJsonWalletFileSnafu { path: dir.to_string_lossy() })?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().is_file() && wildcard_match(&pattern, &file_name) {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => { backtrack = Some((p, n)); p += 1; }
+            Some(&c) if c == b'?' || c == name[n] => { p += 1; n += 1; }
+            _ => match backtrack {
+                Some((bp, bn)) => { p = bp + 1; n = bn + 1; backtrack = Some((bp, bn + 1)); }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Signs a message in the Solana off-chain message format (v0).
+pub(crate) fn sign_message(keypair: &KeypairSerde, message: &[u8]) -> WalletResult<Signature> {
+    OffchainMessage::new(0, message).context(OffchainMessageSnafu)?
+        .sign(&keypair.0).context(OffchainMessageSnafu)
+}
+
+pub(crate) fn verify_message(pubkey: &PubkeySerde, signature: &Signature, message: &[u8]) -> WalletResult<bool> {
+    OffchainMessage::new(0, message).context(OffchainMessageSnafu)?
+        .verify(&pubkey.0, signature).context(OffchainMessageSnafu)
+}
+
+/// Vanity address pattern for keypair grinding.
+#[derive(Debug, Clone)]
+pub(crate) struct VanityPattern {
+    prefix: String,
+    suffix: String,
+    ignore_case: bool,
+}
+
+impl VanityPattern {
+    const BASE58_ALPHABET: &'static str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+    pub(crate) fn new(prefix: String, suffix: String, ignore_case: bool) -> WalletResult<Self> {
+        let is_base58 = |c: char| match ignore_case {
+            true => Self::BASE58_ALPHABET.contains(c.to_ascii_lowercase())
+                || Self::BASE58_ALPHABET.contains(c.to_ascii_uppercase()),
+            false => Self::BASE58_ALPHABET.contains(c),
+        };
+        if let Some(c) = prefix.chars().chain(suffix.chars()).find(|c| !is_base58(*c)) {
+            return Err(WalletError::InvalidVanityPattern { msg: format!("'{c}' is not a base58 character") });
+        }
+        if prefix.is_empty() && suffix.is_empty() {
+            return Err(WalletError::InvalidVanityPattern { msg: "prefix or suffix is required".into() });
+        }
+        let (prefix, suffix) = match ignore_case {
+            true => (prefix.to_lowercase(), suffix.to_lowercase()),
+            false => (prefix, suffix),
+        };
+        Ok(Self { prefix, suffix, ignore_case })
+    }
+
+    pub(crate) fn matches(&self, pubkey: &Pubkey) -> bool {
+        let address = pubkey.to_string();
+        let address = match self.ignore_case {
+            true => address.to_lowercase(),
+            false => address,
+        };
+        address.starts_with(&self.prefix) && address.ends_with(&self.suffix)
+    }
+}
+
+/// Generates keypairs until `count` of them match the pattern, using `threads` OS threads.
+/// Progress is reported to stderr every second.
+pub(crate) fn grind_wallets(pattern: &VanityPattern, count: usize, threads: usize) -> KeypairList {
+    let attempts = AtomicU64::new(0);
+    let found = Mutex::new(Vec::with_capacity(count));
+    let done = AtomicBool::new(count == 0);
+    let start_time = std::time::Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    let kp = Keypair::new();
+                    attempts.fetch_add(1, Ordering::Relaxed);
+                    if !pattern.matches(&kp.pubkey()) { continue }
+                    let mut found = found.lock().expect("not poisoned");
+                    if found.len() < count {
+                        eprintln!("found {}", kp.pubkey());
+                        found.push(KeypairSerde(kp));
+                    }
+                    if found.len() >= count {
+                        done.store(true, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+        let mut last_report = std::time::Instant::now();
+        while !done.load(Ordering::Relaxed) {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            if last_report.elapsed().as_secs() < 1 { continue }
+            last_report = std::time::Instant::now();
+            let attempts = attempts.load(Ordering::Relaxed);
+            let rate = attempts as f64 / start_time.elapsed().as_secs_f64();
+            let found = found.lock().expect("not poisoned").len();
+            eprintln!("searched {attempts} keypairs ({rate:.0}/s), found {found}/{count}");
+        }
+    });
+    KeypairList(found.into_inner().expect("not poisoned"))
+}
+
 pub(crate) async fn transfer_sol(
     rpc_client: &RpcClient,
     sender: &(dyn Signer + Sync),
     receiver: &Pubkey,
     lamports: u64,
-    recent_blockhash: Option<solana_sdk::hash::Hash>,
+    blockhashes: &BlockhashCache,
     payer: Option<&(dyn Signer + Sync)>,
     memo: Option<impl AsRef<str>>,
 ) -> WalletResult<Signature> {
+    let recent_blockhash = blockhashes.get().await.context(WalletRpcSnafu)?.hash;
+    let tx = transfer_sol_tx(sender, receiver, lamports, recent_blockhash, payer, memo);
+    rpc_client.send_transaction(&tx).await.context(WalletRpcSnafu)
+}
+
+/// Signed SOL transfer transaction.
+pub(crate) fn transfer_sol_tx(
+    sender: &(dyn Signer + Sync),
+    receiver: &Pubkey,
+    lamports: u64,
+    recent_blockhash: Hash,
+    payer: Option<&(dyn Signer + Sync)>,
+    memo: Option<impl AsRef<str>>,
+) -> Transaction {
     let sender_pk = &sender.pubkey();
     let mut instructions = [
         system_instruction::transfer(sender_pk, receiver, lamports),
     ].into_iter().collect::<Vec<_>>();
     let instructions = instructions.with_memo(memo);
-    let recent_blockhash = match recent_blockhash {
-        Some(x) => x,
-        None => rpc_client.get_latest_blockhash().await.context(WalletRpcSnafu)?,
-    };
 
     let payer_pk = payer.map(|kp| kp.pubkey());
-    let tx = Transaction::new_signed_with_payer(
+    Transaction::new_signed_with_payer(
         &instructions, payer_pk.as_ref(), &[sender], recent_blockhash
-    );
-    rpc_client.send_transaction(&tx).await.context(WalletRpcSnafu)
+    )
 }
 
 pub(crate) type WalletResult<T> = Result<T, WalletError>;
@@ -81,18 +325,35 @@ pub(crate) enum WalletError {
     #[snafu(display("Not implemented yet: {msg}"))]
     NotImplementedYet { msg: &'static str },
     #[snafu(display("RPC Error: {source}"))]
-    WalletRpcError { source: solana_client::client_error::ClientError },
+    WalletRpcError {
+        #[snafu(source(from(solana_client::client_error::ClientError, Box::new)))]
+        source: Box<solana_client::client_error::ClientError>,
+    },
     #[snafu(display("Invalid wallet save dir: {path}"))]
     InvalidWalletSaveDir { path: String },
     #[snafu(display("Can't serialize the generated wallet into json format: {source}"))]
     SerializeWalletIntoJsonError { source: serde_json::Error },
+    #[snafu(display("Wallet file already exists (use --force to overwrite): path: {path}"))]
+    WalletFileExists { path: String },
+    #[snafu(display("Wallets #{first} and #{second} would be saved to the same file: path: {path}"))]
+    DuplicateWalletFile { path: String, first: usize, second: usize },
     #[snafu(display("Can't save generated wallet to file: path: {path}; cause: {source}"))]
     SaveJsonWalletToFileError { path: String, source: std::io::Error },
     #[snafu(display("Can't read keypair json file: path: {path}; cause: {source}"))]
     ReadJsonWalletFileError { path: String, source: std::io::Error },
     #[snafu(display("Can't parse keypair json file: path: {path}; cause: {source}"))]
     ParseJsonWalletFileError { path: String, source: serde_json::Error },
+    #[snafu(display("Invalid keypair file: path: {path}; cause: {msg}"))]
+    InvalidKeypairFile { path: String, msg: String },
     ProgramError { source: solana_sdk::program_error::ProgramError },
+    #[snafu(display("Can't read message file: path: {path}; cause: {source}"))]
+    ReadMessageFileError { path: String, source: std::io::Error },
+    #[snafu(display("Invalid off-chain message: {source}"))]
+    OffchainMessageError { source: solana_sdk::sanitize::SanitizeError },
+    #[snafu(display("Invalid message signature"))]
+    InvalidMessageSignature,
+    #[snafu(display("Invalid vanity pattern: {msg}"))]
+    InvalidVanityPattern { msg: String },
 }
 
 pub trait WithMemo {
@@ -113,3 +374,215 @@ impl WithMemo for Vec<Instruction> {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_match_literal() {
+        assert!(wildcard_match("p1w0.json", "p1w0.json"));
+        assert!(!wildcard_match("p1w0.json", "p1w1.json"));
+        assert!(!wildcard_match("p1w0.json", "p1w0.json.bak"));
+        assert!(!wildcard_match("p1w0.json", "p1w0"));
+    }
+
+    #[test]
+    fn wildcard_match_star() {
+        assert!(wildcard_match("*.json", "id.json"));
+        assert!(wildcard_match("*.json", ".json"));
+        assert!(wildcard_match("p1w*.json", "p1w12.json"));
+        assert!(wildcard_match("*w*.json", "p1w1.json.json"));
+        assert!(wildcard_match("*", "anything"));
+        assert!(!wildcard_match("*.json", "id.yaml"));
+        assert!(!wildcard_match("p1w*.json", "p2w1.json"));
+    }
+
+    #[test]
+    fn wildcard_match_question_mark() {
+        assert!(wildcard_match("p?w?.json", "p1w2.json"));
+        assert!(!wildcard_match("p?w?.json", "p1w12.json"));
+        assert!(!wildcard_match("id?.json", "id.json"));
+    }
+
+    #[test]
+    fn sign_and_verify_message() {
+        let kp = KeypairSerde(Keypair::new());
+        let signature = sign_message(&kp, b"hello").unwrap();
+        assert!(verify_message(&kp.pubkey(), &signature, b"hello").unwrap());
+        assert!(!verify_message(&kp.pubkey(), &signature, b"hello!").unwrap());
+        // an empty message is not a valid off-chain message
+        assert!(verify_message(&kp.pubkey(), &signature, b"").is_err());
+        assert!(sign_message(&kp, b"").is_err());
+        assert!(!verify_message(&KeypairSerde(Keypair::new()).pubkey(), &signature, b"hello").unwrap());
+    }
+
+    #[test]
+    fn message_signature_is_over_offchain_message() {
+        let kp = KeypairSerde(Keypair::new());
+        let signature = sign_message(&kp, b"hello").unwrap();
+        let serialized = OffchainMessage::new(0, b"hello").unwrap().serialize().unwrap();
+        // the signing domain, header version 0, format 0 (restricted ASCII), length 5 (little endian)
+        assert_eq!(serialized, [b"\xffsolana offchain".as_slice(), &[0, 0, 5, 0], b"hello"].concat());
+        assert_eq!(signature, kp.0.sign_message(&serialized));
+        // a signature over the raw bytes isn't accepted
+        let raw_signature = kp.0.sign_message(b"hello");
+        assert_ne!(signature, raw_signature);
+        assert!(!verify_message(&kp.pubkey(), &raw_signature, b"hello").unwrap());
+    }
+
+    #[test]
+    fn vanity_pattern_requires_base58() {
+        for (prefix, suffix) in [("0", ""), ("", "O"), ("I", ""), ("", "l"), ("ab+", "")] {
+            assert!(VanityPattern::new(prefix.into(), suffix.into(), false).is_err(), "{prefix}/{suffix}");
+        }
+        assert!(VanityPattern::new(String::new(), String::new(), false).is_err());
+        assert!(VanityPattern::new("abc".into(), "XYZ".into(), false).is_ok());
+        // a character is fine if any of its cases is base58
+        for c in ["O", "I", "l", "L", "i"] {
+            assert!(VanityPattern::new(c.into(), String::new(), true).is_ok(), "{c}");
+        }
+        assert!(VanityPattern::new("0".into(), String::new(), true).is_err());
+    }
+
+    #[test]
+    fn vanity_pattern_matching() {
+        let pubkey = Pubkey::from_str_const("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+        let pattern = |prefix: &str, suffix: &str, ignore_case| {
+            VanityPattern::new(prefix.into(), suffix.into(), ignore_case).unwrap()
+        };
+        assert!(pattern("Tok", "", false).matches(&pubkey));
+        assert!(pattern("", "5DA", false).matches(&pubkey));
+        assert!(pattern("Token", "VQ5DA", false).matches(&pubkey));
+        assert!(!pattern("tok", "", false).matches(&pubkey));
+        assert!(!pattern("", "5da", false).matches(&pubkey));
+        assert!(pattern("tok", "", true).matches(&pubkey));
+        assert!(pattern("TOKENKEG", "vq5da", true).matches(&pubkey));
+        assert!(!pattern("toke", "5db", true).matches(&pubkey));
+    }
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("wallet-test-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn touch(&self, name: &str) {
+            std::fs::write(self.0.join(name), "[]").unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn keypairs(count: usize) -> KeypairList {
+        KeypairList((0..count).map(|_| KeypairSerde(Keypair::new())).collect())
+    }
+
+    fn save_options(naming: FileNaming, force: bool) -> SaveOptions {
+        SaveOptions { force, naming, no_secrets: true }
+    }
+
+    #[test]
+    fn wallet_file_naming() {
+        let kp = KeypairSerde(Keypair::new());
+        assert_eq!(wallet_file_name(7, &kp, Some("alice"), FileNaming::Index), "id000007.json");
+        assert_eq!(wallet_file_name(7, &kp, None, FileNaming::Pubkey), format!("{}.json", kp.pubkey()));
+        assert_eq!(wallet_file_name(7, &kp, Some("bob/../x y"), FileNaming::Name), "bob_.._x_y.json");
+        assert_eq!(wallet_file_name(7, &kp, None, FileNaming::Name), "id000007.json");
+    }
+
+    #[tokio::test]
+    async fn save_wallets_writes_readable_keypair_files() {
+        let dir = TempDir::new("save");
+        let wallets = keypairs(2);
+        let wallet_names = [Some("alice".to_string())];
+        save_wallets_to(wallets.clone(), &wallet_names, &dir.0, &save_options(FileNaming::Name, false)).await.unwrap();
+        let files = find_keypair_files(&dir.0).unwrap();
+        assert_eq!(names(&files), ["alice.json", "id000001.json"]);
+        for (file, kp) in files.iter().zip(wallets.0.iter()) {
+            assert_eq!(read_keypair_file(file).unwrap().pubkey().0, kp.pubkey().0);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt as _;
+                assert_eq!(std::fs::metadata(file).unwrap().permissions().mode() & 0o777, 0o600);
+            }
+        }
+        // no temp files are left
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn save_wallets_refuses_existing_files_without_force() {
+        let dir = TempDir::new("existing");
+        dir.touch("id000001.json");
+        let result = save_wallets_to(keypairs(2), &[], &dir.0, &save_options(FileNaming::Index, false)).await;
+        assert!(matches!(result, Err(WalletError::WalletFileExists { .. })));
+        // nothing is written if any file exists
+        assert_eq!(names(&find_keypair_files(&dir.0).unwrap()), ["id000001.json"]);
+        save_wallets_to(keypairs(2), &[], &dir.0, &save_options(FileNaming::Index, true)).await.unwrap();
+        assert!(read_keypair_file(&dir.0.join("id000001.json")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn save_wallets_refuses_duplicate_names() {
+        let dir = TempDir::new("duplicates");
+        let wallet_names = [Some("a b".to_string()), None, Some("a_b".to_string())];
+        let result = save_wallets_to(keypairs(3), &wallet_names, &dir.0, &save_options(FileNaming::Name, true)).await;
+        assert!(matches!(result, Err(WalletError::DuplicateWalletFile { first: 0, second: 2, .. })));
+        assert!(find_keypair_files(&dir.0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_secret_file_refuses_leftover_temp_file() {
+        let dir = TempDir::new("leftover");
+        let path = dir.0.join("secret.json");
+        // the temp file is created with create_new, another writer's file isn't overwritten
+        dir.touch(&format!(".secret.json.{}.tmp", std::process::id()));
+        let error = write_secret_file_atomically(&path, b"[]").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(!path.exists());
+    }
+
+    fn names(files: &[PathBuf]) -> Vec<String> {
+        files.iter().map(|f| f.file_name().unwrap().to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn find_keypair_files_in_directory() {
+        let dir = TempDir::new("dir");
+        for name in ["b.json", "a.json", "notes.txt"] {
+            dir.touch(name);
+        }
+        // only the top level files are taken, a directory isn't descended into
+        std::fs::create_dir(dir.0.join("nested.json")).unwrap();
+        std::fs::write(dir.0.join("nested.json").join("c.json"), "[]").unwrap();
+        let files = find_keypair_files(&dir.0).unwrap();
+        assert_eq!(names(&files), ["a.json", "b.json"]);
+    }
+
+    #[test]
+    fn find_keypair_files_by_pattern() {
+        let dir = TempDir::new("pattern");
+        for name in ["p1w2.json", "p1w10.json", "p2w1.json", "p1w1.yaml"] {
+            dir.touch(name);
+        }
+        assert_eq!(names(&find_keypair_files(&dir.0.join("p1w*.json")).unwrap()), ["p1w10.json", "p1w2.json"]);
+        assert_eq!(names(&find_keypair_files(&dir.0.join("p?w1.*")).unwrap()), ["p1w1.yaml", "p2w1.json"]);
+        assert_eq!(names(&find_keypair_files(&dir.0.join("p2w1.json")).unwrap()), ["p2w1.json"]);
+        assert!(find_keypair_files(&dir.0.join("p3*.json")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_keypair_files_missing_directory() {
+        let dir = TempDir::new("missing");
+        assert!(find_keypair_files(&dir.0.join("absent").join("*.json")).is_err());
+    }
+}
@@ -1,109 +1,298 @@
-use std::future::Future;
-use futures::future::join_all;
+use std::{any::Any, fmt, future::Future, panic::AssertUnwindSafe, sync::Arc, time::Duration};
+use futures::{FutureExt as _, future::join_all};
+use tokio::{sync::Semaphore, time::{Instant, sleep, timeout}};
 
-pub struct Worker<F: Future> { cnt: usize, fut: Vec<Vec<F>> }
+/// How a job ended, after retries if there were any.
+#[derive(Debug)]
+pub enum JobOutcome<T, E> {
+    Ok(T),
+    Err(E),
+    /// with the panic message
+    Panicked(String),
+    TimedOut,
+}
 
-impl<F: Future + 'static> Worker<F> {
-    pub fn new() -> Self {
-        Self::with_batches(std::thread::available_parallelism().unwrap().get())
+#[derive(Debug)]
+pub struct JobReport<T, E> {
+    pub outcome: JobOutcome<T, E>,
+    pub attempts: u32,
+    /// all the attempts and delays between them
+    pub duration: Duration,
+}
+
+/// Failed and timed out jobs are made again until `max_attempts` attempts in total, panicked ones aren't.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 1, delay: Duration::ZERO }
     }
+}
 
-    pub fn with_batches(batches: usize) -> Self {
-        let mut worker = Self {
-            cnt: batches,
-            fut: Vec::with_capacity(batches)
-        };
-        for _ in 0..worker.cnt {
-            worker.fut.push(Vec::new());
+/// Job counts by outcome.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JobSummary {
+    pub ok: usize,
+    pub failed: usize,
+    pub panicked: usize,
+    pub timed_out: usize,
+}
+
+impl JobSummary {
+    pub fn of<T, E>(reports: &[JobReport<T, E>]) -> Self {
+        let mut summary = Self::default();
+        for report in reports {
+            match report.outcome {
+                JobOutcome::Ok(_) => summary.ok += 1,
+                JobOutcome::Err(_) => summary.failed += 1,
+                JobOutcome::Panicked(_) => summary.panicked += 1,
+                JobOutcome::TimedOut => summary.timed_out += 1,
+            }
         }
-        worker
+        summary
     }
+}
 
-    pub fn push(&mut self, fut: F) {
-        let task_index = self.fut.len() % self.cnt;
-        self.fut[task_index].push(fut)
+impl fmt::Display for JobSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ok, {} failed, {} panicked, {} timed out", self.ok, self.failed, self.panicked, self.timed_out)
     }
+}
+
+/// Queue of jobs running at most `limit` at a time, a queued job starts as soon as a running one finishes.
+/// A job makes a future for every attempt.
+pub struct Worker<J> {
+    limit: usize,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    jobs: Vec<J>,
+}
+
+impl<J, F, T, E> Worker<J>
+    where J: FnMut() -> F, F: Future<Output = Result<T, E>>
+{
+    pub fn new() -> Self {
+        Self::with_limit(std::thread::available_parallelism().unwrap().get())
+    }
+
+    pub fn with_limit(limit: usize) -> Self {
+        Self { limit: limit.max(1), timeout: None, retry: RetryPolicy::default(), jobs: Vec::new() }
+    }
+
+    /// Time limit of a job attempt.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn push(&mut self, job: J) {
+        self.jobs.push(job)
+    }
+
+    /// Runs the jobs within the current task, it doesn't require them to be `Send`.
+    /// Reports are in the order of pushing.
+    pub async fn run_single_threaded(self) -> Vec<JobReport<T, E>> {
+        let slots = Semaphore::new(self.limit);
+        let (job_timeout, retry) = (self.timeout, self.retry);
+        join_all(self.jobs.into_iter().map(|job| {
+            let slots = &slots;
+            async move {
+                let _slot = slots.acquire().await.expect("the semaphore is never closed");
+                run_job(job, job_timeout, retry).await
+            }
+        })).await
+    }
+}
 
-    pub async fn run(self) where F: Send {
-        let mut handles = Vec::new();
-        for jobs in self.fut {
+impl<J, F, T, E> Worker<J>
+    where J: FnMut() -> F + Send + 'static, F: Future<Output = Result<T, E>> + Send + 'static, T: Send + 'static, E: Send + 'static
+{
+    /// Spawns the jobs on the runtime threads. Reports are in the order of pushing.
+    pub async fn run(self) -> Vec<JobReport<T, E>> {
+        let slots = Arc::new(Semaphore::new(self.limit));
+        let (job_timeout, retry) = (self.timeout, self.retry);
+        let mut handles = Vec::with_capacity(self.jobs.len());
+        for job in self.jobs {
+            let slot = slots.clone().acquire_owned().await.expect("the semaphore is never closed");
             handles.push(tokio::spawn(async move {
-                for job in jobs { job.await; }
+                let report = run_job(job, job_timeout, retry).await;
+                drop(slot);
+                report
             }));
         }
-        join_all(handles).await;
-    }
-
-    pub async fn run_single_threaded(self, batch_size: Option<usize>) {
-        let batch_size = match batch_size { Some(x) => x, None => self.cnt };
-        let flat = self.fut.into_iter().flatten().collect::<Vec<_>>();
-        let fut_count = flat.len();
-        let chunks = if fut_count <= batch_size {
-            vec![flat]
-        } else {
-            let chunks_tail = match fut_count % batch_size > 0 {
-                true => 1, false => 0
-            };
-            let chunks_count = fut_count / batch_size + chunks_tail;
-            let mut chunks = Vec::with_capacity(chunks_count);
-            for _ in 0..chunks_count {
-                chunks.push(Vec::new());
-            }
-            for (i, fut) in flat.into_iter().enumerate() {
-                chunks[i % chunks_count].push(fut)
-            }
-            chunks
+        join_all(handles).await.into_iter()
+            // the tasks aren't aborted and panics are caught by `run_job`, the runtime shutdown drops this future too
+            .map(|join_result| join_result.expect("job tasks are neither cancelled nor panic"))
+            .collect()
+    }
+}
+
+async fn run_job<J, F, T, E>(mut job: J, job_timeout: Option<Duration>, retry: RetryPolicy) -> JobReport<T, E>
+    where J: FnMut() -> F, F: Future<Output = Result<T, E>>
+{
+    let start_time = Instant::now();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        // the future is made inside to catch a panic of `job` too
+        let attempt = AssertUnwindSafe(async { job().await }).catch_unwind()
+            .map(|result| match result {
+                Ok(Ok(value)) => JobOutcome::Ok(value),
+                Ok(Err(e)) => JobOutcome::Err(e),
+                Err(payload) => JobOutcome::Panicked(panic_message(payload)),
+            });
+        let outcome = match job_timeout {
+            Some(limit) => timeout(limit, attempt).await.unwrap_or(JobOutcome::TimedOut),
+            None => attempt.await,
         };
-        for jobs in chunks {
-            join_all(jobs).await;
+        let retriable = matches!(outcome, JobOutcome::Err(_) | JobOutcome::TimedOut);
+        if !retriable || attempts >= retry.max_attempts {
+            return JobReport { outcome, attempts, duration: start_time.elapsed() }
         }
+        sleep(retry.delay).await;
     }
 }
 
-impl<F: Future + Send + 'static> Worker<F>
-    where F::Output: Send + Sync
-{
-    pub async fn run_and_collect_results(self) -> Vec<F::Output> {
-        let mut handles = Vec::new();
-        for jobs in self.fut {
-            handles.push(tokio::spawn(async move {
-                let mut res = Vec::new();
-                for job in jobs { res.push(job.await); }
-                res
-            }));
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    /// Counts the jobs running at once.
+    #[derive(Default)]
+    struct InFlight {
+        current: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl InFlight {
+        async fn enter(&self, duration: Duration) {
+            let current = self.current.fetch_add(1, SeqCst) + 1;
+            self.peak.fetch_max(current, SeqCst);
+            sleep(duration).await;
+            self.current.fetch_sub(1, SeqCst);
         }
-        let mut res = Vec::new();
-        for join_result in join_all(handles).await {
-            for job_result in join_result.unwrap().into_iter() {
-                res.push(job_result)
-            }
+    }
+
+    fn values<T: fmt::Debug, E: fmt::Debug>(reports: Vec<JobReport<T, E>>) -> Vec<T> {
+        reports.into_iter()
+            .map(|report| match report.outcome {
+                JobOutcome::Ok(value) => value,
+                outcome => panic!("unexpected outcome: {outcome:?}"),
+            })
+            .collect()
+    }
+
+    /// Later jobs are shorter, so they finish before the earlier ones.
+    fn job_duration(i: u64) -> Duration {
+        Duration::from_millis(40 - 3 * i)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn run_keeps_the_limit_and_the_push_order() {
+        let in_flight = Arc::new(InFlight::default());
+        let mut worker = Worker::with_limit(3);
+        for i in 0..12 {
+            let in_flight = in_flight.clone();
+            worker.push(move || {
+                let in_flight = in_flight.clone();
+                async move {
+                    in_flight.enter(job_duration(i)).await;
+                    Ok::<_, ()>(i)
+                }
+            });
         }
-        res
+        assert_eq!(values(worker.run().await), (0..12).collect::<Vec<_>>());
+        assert_eq!(in_flight.peak.load(SeqCst), 3);
     }
 
-    pub async fn run_all_joined(self) {
-        let mut handles = Vec::new();
-        for jobs in self.fut {
-            handles.push(tokio::spawn(async move {
-                join_all(jobs).await
-            }));
+    #[tokio::test]
+    async fn run_single_threaded_keeps_the_limit_and_the_push_order() {
+        let in_flight = InFlight::default();
+        let mut worker = Worker::with_limit(3);
+        for i in 0..12 {
+            let in_flight = &in_flight;
+            worker.push(move || async move {
+                in_flight.enter(job_duration(i)).await;
+                Ok::<_, ()>(i)
+            });
         }
-        join_all(handles).await;
+        assert_eq!(values(worker.run_single_threaded().await), (0..12).collect::<Vec<_>>());
+        assert_eq!(in_flight.peak.load(SeqCst), 3);
     }
 
-    pub async fn run_all_joined_and_collect_results(self) -> Vec<F::Output> {
-        let mut handles = Vec::new();
-        for jobs in self.fut {
-            handles.push(tokio::spawn(async move {
-                join_all(jobs).await
-            }));
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn run_reports_every_outcome() {
+        let mut worker = Worker::with_limit(4).with_timeout(Duration::from_millis(50));
+        for i in 0..4 {
+            worker.push(move || async move {
+                match i {
+                    0 => Ok(i),
+                    1 => Err("failed"),
+                    2 => panic!("job {i} panicked"),
+                    _ => {
+                        sleep(Duration::from_secs(10)).await;
+                        Ok(i)
+                    }
+                }
+            });
         }
-        let mut res = Vec::new();
-        for join_result in join_all(handles).await {
-            for job_result in join_result.unwrap().into_iter() {
-                res.push(job_result)
-            }
+        let reports = worker.run().await;
+        assert!(matches!(reports[0].outcome, JobOutcome::Ok(0)));
+        assert!(matches!(reports[1].outcome, JobOutcome::Err("failed")));
+        assert!(matches!(&reports[2].outcome, JobOutcome::Panicked(message) if message == "job 2 panicked"));
+        assert!(matches!(reports[3].outcome, JobOutcome::TimedOut));
+        assert!(reports.iter().all(|report| report.attempts == 1));
+        assert!(reports[3].duration >= Duration::from_millis(50));
+        assert_eq!(JobSummary::of(&reports).to_string(), "1 ok, 1 failed, 1 panicked, 1 timed out");
+    }
+
+    #[tokio::test]
+    async fn failed_and_timed_out_jobs_are_retried() {
+        let retry = RetryPolicy { max_attempts: 3, delay: Duration::from_millis(1) };
+        let calls = [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+        let mut worker = Worker::with_limit(4).with_timeout(Duration::from_millis(20)).with_retry(retry);
+        for (i, calls) in calls.iter().enumerate() {
+            worker.push(move || async move {
+                let call = calls.fetch_add(1, SeqCst) + 1;
+                match i {
+                    // succeeds on the second attempt
+                    0 if call < 2 => Err(call),
+                    0 => Ok(call),
+                    1 => Err(call),
+                    2 => {
+                        sleep(Duration::from_secs(10)).await;
+                        Ok(call)
+                    }
+                    _ => panic!("not retried"),
+                }
+            });
         }
-        res
+        let reports = worker.run_single_threaded().await;
+        assert!(matches!(reports[0].outcome, JobOutcome::Ok(2)));
+        assert!(matches!(reports[1].outcome, JobOutcome::Err(3)));
+        assert!(matches!(reports[2].outcome, JobOutcome::TimedOut));
+        assert!(matches!(reports[3].outcome, JobOutcome::Panicked(_)));
+        assert_eq!(reports.iter().map(|report| report.attempts).collect::<Vec<_>>(), [2, 3, 3, 1]);
+        assert_eq!(calls.iter().map(|calls| calls.load(SeqCst)).collect::<Vec<_>>(), [2, 3, 3, 1]);
     }
 }